@@ -0,0 +1,253 @@
+// Measures inflate throughput, and compares it to an inflater that decodes every code by walking
+// the Huffman tree one bit at a time, as the inflater did before it used lookup tables. Both
+// decode the same stream held in memory into a `Vec`, and their outputs are checked.
+//
+// Run with `cargo bench`, optionally followed by `-- <file>` to inflate that file instead of
+// generated text.
+
+use essam::deflate::{DeflateDecoder, DeflateEncoder, DeflateOptions};
+use essam::huffman::{HuffmanTable, HuffmanTree};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+const MIN_DURATION: Duration = Duration::from_secs(1);
+
+// The tables of RFC1951 section 3.2.5, and the order of the code length code lengths.
+const LENGTH_BASE: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [usize; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [usize; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+// The input bits of `tree_walk_inflate`.
+struct Bits<'a> {
+    input: &'a [u8],
+    buffer: u64,
+    length: usize,
+}
+
+fn main() {
+    let data = match std::env::args().nth(1).filter(|arg| arg != "--bench") {
+        Some(path) => std::fs::read(path).expect("failed to read the input file"),
+        None => generate_text(4 << 20),
+    };
+
+    let mut encoder = DeflateEncoder::new(Vec::new(), DeflateOptions::default());
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let mut output = Vec::with_capacity(data.len());
+    let table_throughput = measure(data.len(), || {
+        output.clear();
+        DeflateDecoder::new(compressed.as_slice())
+            .read_to_end(&mut output)
+            .unwrap();
+    });
+    assert!(output == data);
+
+    let tree_throughput = measure(data.len(), || {
+        output.clear();
+        tree_walk_inflate(&compressed, &mut output);
+    });
+    assert!(output == data);
+
+    println!("inflate:           {table_throughput:8.1} MB/s");
+    println!("tree walk inflate: {tree_throughput:8.1} MB/s");
+}
+
+// Decompresses a whole deflate stream, which is assumed to be valid.
+fn tree_walk_inflate(compressed: &[u8], output: &mut Vec<u8>) {
+    let mut bits = Bits::new(compressed);
+
+    loop {
+        let bfinal = bits.read_bits(1) == 1;
+
+        match bits.read_bits(2) {
+            0b00 => {
+                bits.align_to_byte();
+                let len = bits.read_bits(16);
+                bits.read_bits(16); // NLEN
+
+                for _ in 0..len {
+                    output.push(bits.read_bits(8) as u8);
+                }
+            }
+            0b01 => {
+                let mut literal_lengths = [8; 288];
+                literal_lengths[144..256].fill(9);
+                literal_lengths[256..280].fill(7);
+
+                let literal_tree = tree_from_lengths(&literal_lengths);
+                let distance_tree = tree_from_lengths(&[5; 30]);
+                inflate_block(&mut bits, &literal_tree, &distance_tree, output);
+            }
+            0b10 => {
+                let (literal_tree, distance_tree) = read_dynamic_trees(&mut bits);
+                inflate_block(&mut bits, &literal_tree, &distance_tree, output);
+            }
+            _ => panic!("invalid block type"),
+        }
+
+        if bfinal {
+            break;
+        }
+    }
+}
+
+fn read_dynamic_trees(bits: &mut Bits) -> (HuffmanTree, HuffmanTree) {
+    let num_literals = bits.read_bits(5) + 257;
+    let num_distances = bits.read_bits(5) + 1;
+    let num_code_length_codes = bits.read_bits(4) + 4;
+
+    let mut code_length_lengths = [0; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..num_code_length_codes] {
+        code_length_lengths[symbol] = bits.read_bits(3) as u8;
+    }
+    let code_length_tree = tree_from_lengths(&code_length_lengths);
+
+    let mut lengths = Vec::with_capacity(num_literals + num_distances);
+    while lengths.len() < num_literals + num_distances {
+        let (length, num_repeated) = match bits.read_symbol(&code_length_tree) {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().unwrap(), 3 + bits.read_bits(2)),
+            17 => (0, 3 + bits.read_bits(3)),
+            _ => (0, 11 + bits.read_bits(7)),
+        };
+
+        lengths.extend(std::iter::repeat_n(length, num_repeated));
+    }
+
+    (
+        tree_from_lengths(&lengths[..num_literals]),
+        tree_from_lengths(&lengths[num_literals..]),
+    )
+}
+
+fn inflate_block(
+    bits: &mut Bits,
+    literal_tree: &HuffmanTree,
+    distance_tree: &HuffmanTree,
+    output: &mut Vec<u8>,
+) {
+    loop {
+        let symbol = bits.read_symbol(literal_tree);
+
+        if symbol < 256 {
+            output.push(symbol as u8);
+        } else if symbol == 256 {
+            return;
+        } else {
+            let length_idx = symbol - 257;
+            let length = LENGTH_BASE[length_idx] + bits.read_bits(LENGTH_EXTRA_BITS[length_idx]);
+
+            let distance_idx = bits.read_symbol(distance_tree);
+            let distance =
+                DISTANCE_BASE[distance_idx] + bits.read_bits(DISTANCE_EXTRA_BITS[distance_idx]);
+
+            let start = output.len() - distance;
+            for i in start..start + length {
+                output.push(output[i]);
+            }
+        }
+    }
+}
+
+fn tree_from_lengths(lengths: &[u8]) -> HuffmanTree {
+    HuffmanTree::from(&HuffmanTable::from_lengths(lengths))
+}
+
+impl<'a> Bits<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            buffer: 0,
+            length: 0,
+        }
+    }
+
+    fn read_bits(&mut self, length: usize) -> usize {
+        while self.length < length {
+            let (&byte, rest) = self.input.split_first().expect("truncated stream");
+            self.buffer |= (byte as u64) << self.length;
+            self.length += 8;
+            self.input = rest;
+        }
+
+        let value = self.buffer & ((1 << length) - 1);
+        self.buffer >>= length;
+        self.length -= length;
+
+        value as usize
+    }
+
+    fn align_to_byte(&mut self) {
+        self.read_bits(self.length % 8);
+    }
+
+    fn read_symbol(&mut self, tree: &HuffmanTree) -> usize {
+        let mut iter = tree.create_walk_iter();
+        while !iter.leaf {
+            let bit = self.read_bits(1) != 0;
+            iter = tree.walk(iter, bit).expect("invalid code");
+        }
+
+        iter.idx
+    }
+}
+
+// Runs `f` repeatedly for a while, and returns the number of millions of units processed per second.
+fn measure(num_units: usize, mut f: impl FnMut()) -> f64 {
+    let start = Instant::now();
+    let mut num_runs = 0;
+
+    while num_runs == 0 || start.elapsed() < MIN_DURATION {
+        f();
+        num_runs += 1;
+    }
+
+    (num_runs * num_units) as f64 / start.elapsed().as_secs_f64() / 1e6
+}
+
+// Generates words with a skewed distribution, which compresses roughly like English text.
+fn generate_text(len: usize) -> Vec<u8> {
+    let mut state = 1;
+    let words: Vec<Vec<u8>> = (0..2000)
+        .map(|_| {
+            let word_len = 1 + next_random(&mut state) % 10;
+            (0..word_len)
+                .map(|_| b'a' + (next_random(&mut state) % 26) as u8)
+                .collect()
+        })
+        .collect();
+
+    let mut text = Vec::with_capacity(len + 16);
+    while text.len() < len {
+        // Multiplying two uniform numbers favors the first words.
+        let idx = (next_random(&mut state) % 64 * (next_random(&mut state) % 64)) as usize;
+        text.extend_from_slice(&words[idx % words.len()]);
+        text.push(if idx.is_multiple_of(13) { b'\n' } else { b' ' });
+    }
+
+    text.truncate(len);
+    text
+}
+
+fn next_random(state: &mut u32) -> u32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    *state
+}
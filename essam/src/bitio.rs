@@ -22,13 +22,7 @@ impl<W: Write> Write for BitWriter<W> {
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        if self.length > 0 {
-            let bytes = self.buffer.to_le_bytes();
-            let num_bytes = (self.length + 7) / 8;
-
-            self.write(&bytes[0..num_bytes])?;
-        }
-
+        self.align_to_byte()?;
         self.writer.flush()
     }
 
@@ -56,11 +50,11 @@ impl<W: Write> BitWriter<W> {
         assert!(length <= Self::BUF_NBITS);
 
         if self.length + length < Self::BUF_NBITS {
-            self.buffer = self.buffer | (data << self.length);
+            self.buffer |= data << self.length;
             self.length += length;
         } else {
             let concatenated_data = self.buffer | data.overflowing_shl(self.length as u32).0;
-            self.write(&concatenated_data.to_le_bytes())?;
+            self.write_all(&concatenated_data.to_le_bytes())?;
 
             self.buffer = data
                 .overflowing_shr((Self::BUF_NBITS - self.length) as u32)
@@ -70,6 +64,29 @@ impl<W: Write> BitWriter<W> {
 
         Ok(())
     }
+
+    /// Returns the underlying writer, dropping any buffered bits. `flush` or `align_to_byte` should
+    /// be called first.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// The number of bits written that are still buffered.
+    pub fn num_buffered_bits(&self) -> usize {
+        self.length
+    }
+
+    /// Pads the written bits with zeros up to the byte boundary, and writes out all the buffered
+    /// bytes, so that whole bytes can be written directly afterwards.
+    pub fn align_to_byte(&mut self) -> std::io::Result<()> {
+        let bytes = self.buffer.to_le_bytes();
+        let num_bytes = self.length.div_ceil(8);
+
+        self.buffer = 0;
+        self.length = 0;
+
+        self.writer.write_all(&bytes[0..num_bytes])
+    }
 }
 
 impl<R: Read> Read for BitReader<R> {
@@ -108,7 +125,12 @@ impl<R: Read> BitReader<R> {
     pub fn read_bits(&mut self, length: usize) -> std::io::Result<u64> {
         assert!(length <= Self::BUF_NBITS);
 
-        let mask = (!(0 as u64))
+        // The mask below can't be computed for zero bits, as shifting by 64 is a no-op.
+        if length == 0 {
+            return Ok(0);
+        }
+
+        let mask = (!0_u64)
             .overflowing_shr((Self::BUF_NBITS - length) as u32)
             .0;
 
@@ -138,22 +160,6 @@ impl<R: Read> BitReader<R> {
             Ok(result)
         }
     }
-
-    pub fn put_back_extra(&mut self) -> std::io::Result<()>
-    where
-        R: Seek,
-    {
-        // ignore the byte we've already taken bits from.
-        let nbytes = (self.length / 8) as i64;
-
-        self.length = 0;
-        self.buffer = 0;
-        // FIXME
-        // self.seek_relative(-nbytes)
-        self.seek(std::io::SeekFrom::Current(-nbytes))?;
-
-        Ok(())
-    }
 }
 
 impl<R: Read + Seek> Seek for BitReader<R> {
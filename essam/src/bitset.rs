@@ -19,7 +19,7 @@ impl std::fmt::Debug for Bitset {
 
 impl Bitset {
     pub fn with_capacity(capacity: usize) -> Self {
-        let len = (capacity + NUM_BITS - 1) / NUM_BITS;
+        let len = capacity.div_ceil(NUM_BITS);
         Bitset { data: vec![0; len] }
     }
 
@@ -76,7 +76,7 @@ impl Bitset {
 
     pub fn iter(&self) -> impl std::iter::Iterator<Item = usize> + '_ {
         BitsetIterator {
-            bitset: &self,
+            bitset: self,
             current_data: if self.data.is_empty() {
                 0
             } else {
@@ -0,0 +1,227 @@
+// Splits a sequence of LZ77 symbols into blocks where the symbol statistics change.
+//
+// Each deflate block carries its own Huffman codes, so splitting where the statistics change lets
+// each part use codes that fit it better. On the other hand, every dynamic block pays for its own
+// header, so a split is only worth it if the savings in the data are larger than that.
+//
+// Estimating Block Sizes
+// ======================
+// The number of bits needed for the data of a block is estimated by the Shannon entropy of its
+// symbol histograms: a symbol with frequency f out of T symbols costs log2(T / f) bits, so the whole
+// histogram costs T * log2(T) - sum(f * log2(f)) bits. Keeping sum(f * log2(f)) up to date as
+// symbols move from one side of the split to the other makes evaluating every split point of a
+// range linear in its length. The header is estimated by a fixed cost plus a few bits per used
+// symbol. Extra bits don't depend on the split, so they are left out.
+//
+// Splitting
+// =========
+// The range is split at the point with the lowest estimate for both sides, but only if the exact
+// cost (which the caller computes by actually building the codes) of both sides is lower than the
+// exact cost of the whole range. Both sides are then split recursively.
+
+use std::ops::Range;
+
+// Rough estimates of the header of a dynamic block.
+const HEADER_BASE_BITS: f64 = 5.0 + 5.0 + 4.0 + 19.0 * 3.0;
+const HEADER_BITS_PER_USED_SYMBOL: f64 = 4.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BlockItem {
+    pub literal_symbol: u16,
+    pub distance_symbol: Option<u16>,
+}
+
+struct Histogram {
+    freqs: Vec<u32>,
+    total: u32,
+    num_used: u32,
+    // Sum of f * log2(f) over all frequencies.
+    sum_f_log_f: f64,
+}
+
+impl Histogram {
+    fn new(num_symbols: usize) -> Self {
+        Self {
+            freqs: vec![0; num_symbols],
+            total: 0,
+            num_used: 0,
+            sum_f_log_f: 0.0,
+        }
+    }
+
+    fn add(&mut self, symbol: usize) {
+        let freq = self.freqs[symbol];
+
+        self.sum_f_log_f += f_log_f(freq + 1) - f_log_f(freq);
+        self.num_used += (freq == 0) as u32;
+        self.total += 1;
+        self.freqs[symbol] = freq + 1;
+    }
+
+    fn remove(&mut self, symbol: usize) {
+        let freq = self.freqs[symbol];
+
+        self.sum_f_log_f += f_log_f(freq - 1) - f_log_f(freq);
+        self.num_used -= (freq == 1) as u32;
+        self.total -= 1;
+        self.freqs[symbol] = freq - 1;
+    }
+
+    fn entropy_bits(&self) -> f64 {
+        f_log_f(self.total) - self.sum_f_log_f
+    }
+}
+
+struct Histograms {
+    literals: Histogram,
+    distances: Histogram,
+}
+
+impl Histograms {
+    fn new(num_literal_symbols: usize, num_distance_symbols: usize) -> Self {
+        Self {
+            literals: Histogram::new(num_literal_symbols),
+            distances: Histogram::new(num_distance_symbols),
+        }
+    }
+
+    fn add(&mut self, item: &BlockItem) {
+        self.literals.add(item.literal_symbol as usize);
+        if let Some(distance_symbol) = item.distance_symbol {
+            self.distances.add(distance_symbol as usize);
+        }
+    }
+
+    fn remove(&mut self, item: &BlockItem) {
+        self.literals.remove(item.literal_symbol as usize);
+        if let Some(distance_symbol) = item.distance_symbol {
+            self.distances.remove(distance_symbol as usize);
+        }
+    }
+
+    fn estimated_bits(&self) -> f64 {
+        let num_used = self.literals.num_used + self.distances.num_used;
+
+        HEADER_BASE_BITS
+            + HEADER_BITS_PER_USED_SYMBOL * num_used as f64
+            + self.literals.entropy_bits()
+            + self.distances.entropy_bits()
+    }
+}
+
+/// Finds the indices of the items at which new blocks should start, in increasing order. Blocks
+/// are never shorter than `min_block_len` items. `block_cost` returns the exact cost in bits of a
+/// block holding the given range of items.
+pub fn find_split_points(
+    items: &[BlockItem],
+    min_block_len: usize,
+    mut block_cost: impl FnMut(Range<usize>) -> usize,
+) -> Vec<usize> {
+    let num_literal_symbols = items
+        .iter()
+        .map(|item| item.literal_symbol as usize + 1)
+        .max()
+        .unwrap_or(0);
+    let num_distance_symbols = items
+        .iter()
+        .filter_map(|item| item.distance_symbol)
+        .map(|symbol| symbol as usize + 1)
+        .max()
+        .unwrap_or(0);
+
+    let mut split_points = Vec::new();
+    let context = SplitContext {
+        items,
+        min_block_len,
+        num_literal_symbols,
+        num_distance_symbols,
+    };
+
+    let cost = block_cost(0..items.len());
+    context.split(0..items.len(), cost, &mut block_cost, &mut split_points);
+
+    split_points
+}
+
+struct SplitContext<'a> {
+    items: &'a [BlockItem],
+    min_block_len: usize,
+    num_literal_symbols: usize,
+    num_distance_symbols: usize,
+}
+
+impl SplitContext<'_> {
+    fn split(
+        &self,
+        range: Range<usize>,
+        cost: usize,
+        block_cost: &mut impl FnMut(Range<usize>) -> usize,
+        split_points: &mut Vec<usize>,
+    ) {
+        let Some(split_point) = self.best_split_point(range.clone()) else {
+            return;
+        };
+
+        let left_cost = block_cost(range.start..split_point);
+        let right_cost = block_cost(split_point..range.end);
+
+        if left_cost + right_cost >= cost {
+            return;
+        }
+
+        // Push the split points in order.
+        self.split(
+            range.start..split_point,
+            left_cost,
+            block_cost,
+            split_points,
+        );
+        split_points.push(split_point);
+        self.split(split_point..range.end, right_cost, block_cost, split_points);
+    }
+
+    fn best_split_point(&self, range: Range<usize>) -> Option<usize> {
+        if range.len() < 2 * self.min_block_len {
+            return None;
+        }
+
+        let mut left = Histograms::new(self.num_literal_symbols, self.num_distance_symbols);
+        let mut right = Histograms::new(self.num_literal_symbols, self.num_distance_symbols);
+
+        let first_split_point = range.start + self.min_block_len;
+        let last_split_point = range.end - self.min_block_len;
+
+        for item in &self.items[range.start..first_split_point] {
+            left.add(item);
+        }
+        for item in &self.items[first_split_point..range.end] {
+            right.add(item);
+        }
+
+        let mut best_split_point = first_split_point;
+        let mut best_bits = left.estimated_bits() + right.estimated_bits();
+
+        for split_point in first_split_point + 1..=last_split_point {
+            let item = &self.items[split_point - 1];
+            left.add(item);
+            right.remove(item);
+
+            let bits = left.estimated_bits() + right.estimated_bits();
+            if bits < best_bits {
+                best_bits = bits;
+                best_split_point = split_point;
+            }
+        }
+
+        Some(best_split_point)
+    }
+}
+
+fn f_log_f(freq: u32) -> f64 {
+    if freq == 0 {
+        0.0
+    } else {
+        let freq = freq as f64;
+        freq * freq.log2()
+    }
+}
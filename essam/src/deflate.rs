@@ -1,11 +1,20 @@
-use crate::bitio::{BitReader, BitWriter};
-use crate::huffman::{HuffmanTable, HuffmanTree};
-use std::io::{Read, Seek, Write};
+use crate::bitio::BitWriter;
+use crate::block_split::{find_split_points, BlockItem};
+use crate::error::DecodeError;
+use crate::huffman::{DecodeTable, HuffmanTable};
+use crate::lz77::{Match, MatchFinder, MatchFinderConfig, MAX_MATCH, MIN_MATCH, WINDOW_SIZE};
+use crate::optimal_parse::{optimal_parse, BlockMatches, CostModel};
+use std::io::{Read, Seek, SeekFrom, Write};
 
 const NUM_LITERAL_SYMBOLS: usize = 286;
 const NUM_LENGTH_SYMBOLS: usize = 19;
 const NUM_DISTANCE_SYMBOLS: usize = 30;
+// The fixed codes also assign codes to two literal/length and two distance symbols that never occur.
+const NUM_FIXED_LITERAL_SYMBOLS: usize = 288;
+const NUM_FIXED_DISTANCE_SYMBOLS: usize = 32;
 const EOF: usize = 256;
+const MAX_STORED_BLOCK_SIZE: usize = 65535;
+const FIRST_LENGTH_SYMBOL: usize = 257;
 
 const REPEAT_PREV_3_6_SYMBOL: u16 = 16;
 const REPEAT_PREV_3_6_ARG_LEN: usize = 2;
@@ -22,13 +31,96 @@ const LENGTH_ORDER: [usize; NUM_LENGTH_SYMBOLS] = [
 
 const MAX_CODE_LENGTH: usize = 15;
 const MAX_LENGTH_CODE_LENGTH: usize = 7;
+// The number of bits looked up at once when decoding, like in zlib. Longer codes take two lookups.
+const LITERAL_TABLE_BITS: usize = 9;
+const DISTANCE_TABLE_BITS: usize = 6;
 const CODE_LENGTH_CODE_LENGTH_LEN: usize = 3; // Absolutely ridiculous
 
+// Base match lengths and number of extra bits for length symbols 257..285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+// Base distances and number of extra bits for distance symbols 0..29.
+const DISTANCE_BASE: [u16; NUM_DISTANCE_SYMBOLS] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; NUM_DISTANCE_SYMBOLS] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+pub const MAX_LEVEL: u8 = 9;
+const MAX_OPTIMAL_PARSE_ITERATIONS: usize = 15;
+// Blocks shorter than this (in literals and matches) are not worth considering for splitting.
+const MIN_SPLIT_BLOCK_LEN: usize = 1024;
+
 pub struct DeflateOptions {
+    /// The number of bytes compressed at once. Unless block splitting is disabled, these are split
+    /// further into the blocks that are actually written.
     pub block_size: usize,
+    /// Splits blocks where the statistics of the data change, if the new Huffman codes save more
+    /// than the cost of their headers.
+    pub split_blocks: bool,
+    /// Compression level from 0 (no compression) to 9 (best compression), like zlib.
+    pub level: u8,
+    /// Uses optimal parsing, refined over several iterations, on top of level 9. This is much
+    /// slower, and is meant for data that is compressed once and decompressed many times.
+    pub ultra: bool,
+    /// Data that's likely to appear in the input, which matches can refer back to as if it came
+    /// right before it. The decoder needs the same dictionary. Only the last 32 KiB are used.
+    pub dictionary: Option<Vec<u8>>,
+    /// Records statistics about every block that is written in a `CompressionReport`.
+    pub stats: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParseStrategy {
+    /// No matching at all, everything is sent in stored blocks.
+    Store,
+    /// Always takes the longest match at the current position.
+    Greedy,
+    /// Emits a literal instead of the match at the current position if the next position has a
+    /// longer match.
+    Lazy,
+    /// Same as lazy, but also looks two positions ahead.
+    Lazy2,
+    /// Finds the cheapest parse given the code lengths of the previous parse, until the size of the
+    /// block stops improving.
+    Optimal,
+}
+
+struct LevelConfig {
+    match_finder: MatchFinderConfig,
+    // Matches at least this long are taken right away without looking for a longer match at the
+    // next position. For the greedy strategy, the positions a match at least this long skips are
+    // not inserted into the hash chains.
+    lazy_length: usize,
+    strategy: ParseStrategy,
+}
+
+/// Compresses everything written to it into a deflate stream. `finish` must be called to write the
+/// final block.
+pub struct DeflateEncoder<W: Write> {
+    writer: BitWriter<W>,
+    options: DeflateOptions,
+    config: LevelConfig,
+    match_finder: MatchFinder,
+    block: Block,
+    // Written bytes that were not compressed yet. A block is only compressed once more bytes than
+    // the block size are buffered, so that it's known whether it's the final block.
+    pending: Vec<u8>,
+    report: Option<CompressionReport>,
 }
 
 struct Block {
+    // The uncompressed bytes of the block, needed in case it's sent as a stored block.
+    bytes: Vec<u8>,
     symbols: Vec<u16>,
     literal_freqs: [u32; NUM_LITERAL_SYMBOLS],
     distance_freqs: [u32; NUM_DISTANCE_SYMBOLS],
@@ -39,15 +131,365 @@ struct BlockCompressionInfo {
     num_distance_codes: usize,
 }
 
+// The codes of a block sent with dynamic Huffman codes, and what's needed to write them.
+struct DynamicTables {
+    info: BlockCompressionInfo,
+    literal_table: HuffmanTable,
+    distance_table: HuffmanTable,
+    header: HuffmanTablesHeader,
+}
+
+struct HuffmanTablesHeader {
+    num_code_length_codes: usize,
+    length_table: HuffmanTable,
+    literal_table_lengths_symbols: Vec<u16>,
+    distance_table_lengths_symbols: Vec<u16>,
+}
+
+/// Statistics about the blocks written by an encoder, for seeing how the options affect the output.
+#[derive(Debug, Clone, Default)]
+pub struct CompressionReport {
+    pub blocks: Vec<BlockReport>,
+}
+
+/// How a block of input was compressed. A block that's too large for a single stored block is
+/// written as several, but still gets a single report.
+#[derive(Debug, Clone)]
+pub struct BlockReport {
+    pub block_type: BlockType,
+    pub input_bytes: usize,
+    /// The size of the block, including its header.
+    pub output_bits: usize,
+    /// The bits before the data: BFINAL and BTYPE, the padding and lengths of stored blocks, and
+    /// the code lengths of dynamic blocks.
+    pub header_bits: usize,
+    /// The number of literals and matches. The bytes of stored blocks all count as literals.
+    pub num_literals: usize,
+    pub num_matches: usize,
+    pub total_match_length: usize,
+    pub total_match_distance: usize,
+    /// The Shannon entropy of the literal/length symbols, in bits per symbol. This is the least a
+    /// prefix code for this block could spend on each of them, not counting the extra bits.
+    pub literal_entropy: f64,
+    /// The bits per literal/length symbol spent by the code that was used, not counting the extra
+    /// bits. The bytes of stored blocks take 8 bits each.
+    pub literal_bits_per_symbol: f64,
+    /// Same as `literal_entropy`, for the distance symbols.
+    pub distance_entropy: f64,
+    /// Same as `literal_bits_per_symbol`, for the distance symbols.
+    pub distance_bits_per_symbol: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockType {
+    Stored,
+    Fixed,
+    Dynamic,
+}
+
+/// Decompresses a deflate stream pulled from the underlying reader. The reader doesn't need to
+/// support seeking: the bytes read past the end of the stream are available from `remaining`.
+pub struct DeflateDecoder<R: Read> {
+    reader: R,
+    inflater: Inflater,
+    // The bytes read from the reader that were not consumed yet are input[input_start..input_end].
+    input: Vec<u8>,
+    input_start: usize,
+    input_end: usize,
+}
+
+/// Decompresses a deflate stream that arrives in arbitrary chunks, without ever blocking. `inflate`
+/// stops once it runs out of input or output, and the next call continues from the same point,
+/// even if that is in the middle of a symbol, a block header or a stored block.
+pub struct Inflater {
+    bits: BitBuffer,
+    window: OutputWindow,
+    state: InflateState,
+    // Whether the current block is the last one.
+    bfinal: bool,
+}
+
+#[derive(Debug)]
+pub struct Status {
+    /// The number of input bytes consumed. These must not be passed to `inflate` again.
+    pub num_read: usize,
+    /// The number of bytes written to the output.
+    pub num_written: usize,
+    pub code: StatusCode,
+}
+
+#[derive(Debug)]
+pub enum StatusCode {
+    /// All the input was consumed, and more is needed to continue.
+    NeedsInput,
+    /// The output is full, and there may be more decompressed data.
+    NeedsOutput,
+    /// The stream ended and all of it was written to the output. The input after the end of the
+    /// stream is not consumed.
+    StreamEnd,
+    Error(DecodeError),
+}
+
+enum InflateState {
+    BlockHeader,
+    StoredBlockHeader,
+    StoredBlock {
+        num_remaining: usize,
+    },
+    DynamicBlockHeader,
+    CodeLengthCodeLengths {
+        sizes: TableSizes,
+        lengths: [u8; NUM_LENGTH_SYMBOLS],
+        num_read: usize,
+    },
+    CodeLengths {
+        sizes: TableSizes,
+        table: DecodeTable,
+        // The code lengths of both alphabets.
+        lengths: Vec<u8>,
+        num_read: usize,
+    },
+    HuffmanBlock {
+        literal_table: DecodeTable,
+        distance_table: DecodeTable,
+    },
+    Done,
+}
+
+// The sizes of the tables of a dynamic block, from HLIT, HDIST and HCLEN.
+#[derive(Clone, Copy)]
+struct TableSizes {
+    num_literals: usize,
+    num_distances: usize,
+    num_code_length_codes: usize,
+}
+
+// Holds the input bits that were consumed but not decoded yet.
+#[derive(Clone, Copy, Default)]
+struct BitBuffer {
+    bits: u64,
+    length: usize,
+}
+
+// Why a decoding step couldn't complete.
+enum StepError {
+    NeedsInput,
+    Invalid(DecodeError),
+}
+
+impl From<DecodeError> for StepError {
+    fn from(error: DecodeError) -> Self {
+        StepError::Invalid(error)
+    }
+}
+
+/// A decoded literal/length symbol, along with the distance of a match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symbol {
+    Literal(u8),
+    EndOfBlock,
+    Match { length: usize, distance: usize },
+}
+
+/// Walks through the blocks of a deflate stream held in memory, and describes how each of them is
+/// encoded instead of decompressing it. Matches aren't checked against the data before them, so
+/// streams that need a preset dictionary can be inspected too.
+pub struct BlockInspector<'a> {
+    data: &'a [u8],
+    // The part of `data` that wasn't pulled into the bit buffer yet.
+    input: &'a [u8],
+    bits: BitBuffer,
+    with_symbols: bool,
+    done: bool,
+}
+
+/// How a block is encoded, as found by `BlockInspector`.
+#[derive(Debug, Clone)]
+pub struct BlockInfo {
+    /// The position of the block in the stream, in bits.
+    pub bit_offset: u64,
+    pub bfinal: bool,
+    pub kind: BlockKind,
+    /// The bits before the data: BFINAL and BTYPE, the padding and LEN/NLEN of stored blocks, and
+    /// the code lengths of dynamic blocks.
+    pub header_bits: u64,
+    /// The bits of the data, including the end-of-block code.
+    pub payload_bits: u64,
+    /// The number of bytes the block decompresses to.
+    pub num_bytes: u64,
+    /// Every literal and match of the block, if they were asked for.
+    pub symbols: Vec<SymbolInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub enum BlockKind {
+    Stored,
+    Fixed,
+    Dynamic(DynamicHeader),
+}
+
+/// The code lengths sent at the start of a dynamic block.
+#[derive(Debug, Clone)]
+pub struct DynamicHeader {
+    /// The number of literal/length codes, HLIT + 257.
+    pub num_literal_codes: usize,
+    /// The number of distance codes, HDIST + 1.
+    pub num_distance_codes: usize,
+    /// The number of code length codes, HCLEN + 4.
+    pub num_code_length_codes: usize,
+    /// The code lengths of the code length alphabet, indexed by symbol.
+    pub code_length_lengths: [u8; NUM_LENGTH_SYMBOLS],
+    /// The code length symbols that encode the two other tables, in order.
+    pub code_length_symbols: Vec<CodeLengthSymbol>,
+    pub literal_lengths: Vec<u8>,
+    pub distance_lengths: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CodeLengthSymbol {
+    /// A code length from 0 to 15, 16 for repeating the previous length, or 17 and 18 for
+    /// repeating zero.
+    pub symbol: u8,
+    /// The number of code lengths it stands for, which is 1 unless it's a repeat.
+    pub num_repeated: usize,
+    /// The bits it took, including the extra bits.
+    pub num_bits: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolInfo {
+    pub symbol: Symbol,
+    /// The bits it took, including the extra bits and the distance code of a match.
+    pub num_bits: u64,
+}
+
+// Keeps the decompressed data that was not read yet, and the last window before it for resolving
+// back-references.
+struct OutputWindow {
+    data: Vec<u8>,
+    // The bytes before this position were already read.
+    num_read: usize,
+}
+
 impl Default for DeflateOptions {
     fn default() -> Self {
-        Self { block_size: 16384 }
+        Self {
+            block_size: 131072,
+            split_blocks: true,
+            level: 6,
+            ultra: false,
+            dictionary: None,
+            stats: false,
+        }
+    }
+}
+
+impl LevelConfig {
+    fn from_options(options: &DeflateOptions) -> Self {
+        if options.ultra {
+            Self {
+                strategy: ParseStrategy::Optimal,
+                ..Self::from_level(MAX_LEVEL)
+            }
+        } else {
+            Self::from_level(options.level)
+        }
+    }
+
+    fn from_level(level: u8) -> Self {
+        // Mostly follows zlib's configuration table.
+        let (window_size, good_length, lazy_length, nice_length, max_chain, strategy) =
+            match level.min(MAX_LEVEL) {
+                0 => (WINDOW_SIZE, 0, 0, 0, 0, ParseStrategy::Store),
+                1 => (WINDOW_SIZE / 4, 4, 4, 8, 4, ParseStrategy::Greedy),
+                2 => (WINDOW_SIZE / 4, 4, 5, 16, 8, ParseStrategy::Greedy),
+                3 => (WINDOW_SIZE / 2, 4, 6, 32, 32, ParseStrategy::Greedy),
+                4 => (WINDOW_SIZE, 4, 4, 16, 16, ParseStrategy::Lazy),
+                5 => (WINDOW_SIZE, 8, 16, 32, 32, ParseStrategy::Lazy),
+                6 => (WINDOW_SIZE, 8, 16, 128, 128, ParseStrategy::Lazy),
+                7 => (WINDOW_SIZE, 8, 32, 128, 256, ParseStrategy::Lazy),
+                8 => (WINDOW_SIZE, 32, 128, 258, 1024, ParseStrategy::Lazy2),
+                _ => (WINDOW_SIZE, 32, 258, 258, 4096, ParseStrategy::Lazy2),
+            };
+
+        Self {
+            match_finder: MatchFinderConfig {
+                window_size,
+                max_chain,
+                good_length,
+                nice_length,
+            },
+            lazy_length,
+            strategy,
+        }
+    }
+}
+
+impl OutputWindow {
+    fn new() -> Self {
+        Self {
+            data: Vec::with_capacity(3 * WINDOW_SIZE),
+            num_read: 0,
+        }
+    }
+
+    // Makes the last window of the bytes part of the history, without ever reading them.
+    fn set_history(&mut self, bytes: &[u8]) {
+        debug_assert!(self.data.is_empty());
+
+        self.data
+            .extend_from_slice(&bytes[bytes.len().saturating_sub(WINDOW_SIZE)..]);
+        self.num_read = self.data.len();
+    }
+
+    fn num_unread(&self) -> usize {
+        self.data.len() - self.num_read
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.data.push(byte);
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    fn copy_match(&mut self, length: usize, distance: usize) -> Result<(), StepError> {
+        if distance > self.data.len() {
+            return Err(DecodeError::DistanceTooFarBack.into());
+        }
+
+        // The match can overlap the bytes it produces, so copy byte by byte.
+        let start = self.data.len() - distance;
+        for idx in start..start + length {
+            self.data.push(self.data[idx]);
+        }
+
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let num_bytes = buf.len().min(self.num_unread());
+
+        buf[0..num_bytes].copy_from_slice(&self.data[self.num_read..self.num_read + num_bytes]);
+        self.num_read += num_bytes;
+
+        // Only keep the window needed for back-references, but don't move the data every time.
+        if self.num_read >= 2 * WINDOW_SIZE {
+            let num_discarded = self.num_read - WINDOW_SIZE;
+
+            self.data.drain(0..num_discarded);
+            self.num_read -= num_discarded;
+        }
+
+        num_bytes
     }
 }
 
 impl Default for Block {
     fn default() -> Self {
         Self {
+            bytes: Vec::new(),
             symbols: Vec::new(),
             literal_freqs: [0; NUM_LITERAL_SYMBOLS],
             distance_freqs: [0; NUM_DISTANCE_SYMBOLS],
@@ -55,63 +497,969 @@ impl Default for Block {
     }
 }
 
-pub fn compress(
-    mut reader: &mut (impl Read + Seek),
-    writer: &mut impl Write,
-    options: DeflateOptions,
-) -> std::io::Result<()> {
-    let mut bit_writer = BitWriter::new(writer);
-    let mut block = Block::default();
+/// Compresses everything the reader has into a deflate stream. Returns the report of the blocks if
+/// `stats` was set in the options.
+pub fn compress(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    options: DeflateOptions,
+) -> std::io::Result<Option<CompressionReport>> {
+    let mut encoder = DeflateEncoder::new(writer, options);
+
+    std::io::copy(reader, &mut encoder)?;
+    let (_, report) = encoder.finish_with_report()?;
+
+    Ok(report)
+}
+
+impl<W: Write> DeflateEncoder<W> {
+    pub fn new(writer: W, mut options: DeflateOptions) -> Self {
+        // Blocks are compressed once `block_size` bytes are pending, so an empty block would never
+        // make progress.
+        options.block_size = options.block_size.max(1);
+        let config = LevelConfig::from_options(&options);
+
+        let mut match_finder = MatchFinder::new(config.match_finder);
+        if let Some(dictionary) = &options.dictionary {
+            match_finder.set_dictionary(dictionary);
+        }
+
+        Self {
+            writer: BitWriter::new(writer),
+            match_finder,
+            block: Block::default(),
+            pending: Vec::with_capacity(options.block_size + 1),
+            report: options.stats.then(CompressionReport::default),
+            options,
+            config,
+        }
+    }
+
+    /// Compresses the remaining bytes as the final block, and returns the underlying writer.
+    pub fn finish(self) -> std::io::Result<W> {
+        let (writer, _) = self.finish_with_report()?;
+        Ok(writer)
+    }
+
+    /// Same as `finish`, but also returns the report of all the blocks if `stats` was set in the
+    /// options.
+    pub fn finish_with_report(mut self) -> std::io::Result<(W, Option<CompressionReport>)> {
+        self.compress_pending(true)?;
+
+        self.writer.flush()?;
+        Ok((self.writer.into_inner(), self.report))
+    }
+
+    /// The report of the blocks written so far, if `stats` was set in the options.
+    pub fn report(&self) -> Option<&CompressionReport> {
+        self.report.as_ref()
+    }
+
+    fn compress_pending(&mut self, bfinal: bool) -> std::io::Result<()> {
+        let writer = &mut self.writer;
+        let block = &mut self.block;
+        let mut report = self.report.as_mut();
+
+        compress_block_gen_symbols(&self.pending, &mut self.match_finder, block, &self.config);
+
+        if self.config.strategy == ParseStrategy::Store {
+            if let Some(report) = report {
+                let output_bits = stored_blocks_cost(block.bytes.len(), writer.num_buffered_bits());
+                report.blocks.push(stored_block_report(block, output_bits));
+            }
+
+            write_stored_blocks(writer, &block.bytes, bfinal)?;
+        } else if self.options.split_blocks {
+            let blocks = split_block(block);
+            for (idx, block) in blocks.iter().enumerate() {
+                let bfinal = bfinal && idx + 1 == blocks.len();
+                write_block(writer, block, bfinal, report.as_deref_mut())?;
+            }
+        } else {
+            write_block(writer, block, bfinal, report)?;
+        }
+
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for DeflateEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut remaining = buf;
+
+        while !remaining.is_empty() {
+            // Only compress a full block once there's at least one more byte after it.
+            if self.pending.len() == self.options.block_size {
+                self.compress_pending(false)?;
+            }
+
+            let num_bytes = remaining
+                .len()
+                .min(self.options.block_size - self.pending.len());
+            self.pending.extend_from_slice(&remaining[0..num_bytes]);
+            remaining = &remaining[num_bytes..];
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Compresses all the bytes written so far, and ends the output at a byte boundary using an
+    /// empty stored block, so that everything written so far can be decompressed (like zlib's
+    /// `Z_SYNC_FLUSH`).
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.pending.is_empty() {
+            self.compress_pending(false)?;
+        }
+
+        write_stored_blocks(&mut self.writer, &[], false)?;
+        self.writer.flush()
+    }
+}
+
+/// Decompresses a deflate stream. The bytes that were read after the end of the stream are put back
+/// by seeking the reader.
+pub fn decompress(
+    reader: &mut (impl Read + Seek),
+    writer: &mut impl Write,
+) -> Result<(), DecodeError> {
+    decompress_with_dictionary(reader, writer, &[])
+}
+
+/// Same as `decompress`, for a stream compressed with a preset dictionary.
+pub fn decompress_with_dictionary(
+    reader: &mut (impl Read + Seek),
+    writer: &mut impl Write,
+    dictionary: &[u8],
+) -> Result<(), DecodeError> {
+    let mut decoder = DeflateDecoder::with_dictionary(&mut *reader, dictionary);
+
+    std::io::copy(&mut decoder, writer)?;
+
+    let num_remaining = decoder.remaining().len();
+    reader.seek(SeekFrom::Current(-(num_remaining as i64)))?;
+
+    writer.flush()?;
+    Ok(())
+}
+
+impl<R: Read> DeflateDecoder<R> {
+    const INPUT_BUFFER_SIZE: usize = 8192;
+
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            inflater: Inflater::new(),
+            input: vec![0; Self::INPUT_BUFFER_SIZE],
+            input_start: 0,
+            input_end: 0,
+        }
+    }
+
+    /// Creates a decoder for a stream compressed with a preset dictionary.
+    pub fn with_dictionary(reader: R, dictionary: &[u8]) -> Self {
+        let mut decoder = Self::new(reader);
+        decoder.inflater.set_dictionary(dictionary);
+        decoder
+    }
+
+    /// The bytes that were read from the underlying reader but not consumed yet. Once the whole
+    /// stream has been read, these are the bytes that follow it.
+    pub fn remaining(&self) -> &[u8] {
+        &self.input[self.input_start..self.input_end]
+    }
+
+    /// Returns the underlying reader. The bytes it had that were already read but not consumed are
+    /// in `remaining`.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Read for DeflateDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let status = self
+                .inflater
+                .inflate(&self.input[self.input_start..self.input_end], buf);
+            self.input_start += status.num_read;
+
+            match status.code {
+                StatusCode::NeedsOutput | StatusCode::StreamEnd => return Ok(status.num_written),
+                StatusCode::Error(error) => return Err(error.into()),
+                StatusCode::NeedsInput if status.num_written > 0 => return Ok(status.num_written),
+                StatusCode::NeedsInput => {
+                    let num_bytes = self.reader.read(&mut self.input)?;
+                    if num_bytes == 0 {
+                        return Err(DecodeError::TruncatedInput.into());
+                    }
+
+                    self.input_start = 0;
+                    self.input_end = num_bytes;
+                }
+            }
+        }
+    }
+}
+
+impl Inflater {
+    pub fn new() -> Self {
+        Self {
+            bits: BitBuffer::default(),
+            window: OutputWindow::new(),
+            state: InflateState::BlockHeader,
+            bfinal: false,
+        }
+    }
+
+    /// Seeds the history with a preset dictionary, which the stream can refer back to. Must be
+    /// called before inflating anything.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        self.window.set_history(dictionary);
+    }
+
+    /// Decompresses as much of the input as possible into the output. Only stops when the input
+    /// is exhausted, the output is full, the stream ends, or the data is invalid.
+    pub fn inflate(&mut self, input: &[u8], output: &mut [u8]) -> Status {
+        let mut remaining_input = input;
+        let mut num_written = 0;
+        let mut needs_input = false;
+
+        let code = loop {
+            num_written += self.window.read(&mut output[num_written..]);
+
+            if matches!(self.state, InflateState::Done) && self.window.num_unread() == 0 {
+                break StatusCode::StreamEnd;
+            }
+            if num_written == output.len() {
+                break StatusCode::NeedsOutput;
+            }
+            if needs_input {
+                break StatusCode::NeedsInput;
+            }
+
+            // Don't decompress much more than fits in the output, since it has to be kept until
+            // the next call.
+            let max_unread = (output.len() - num_written).min(WINDOW_SIZE);
+
+            match self.decompress(&mut remaining_input, max_unread) {
+                Ok(()) => {}
+                Err(StepError::NeedsInput) => needs_input = true,
+                Err(StepError::Invalid(error)) => break StatusCode::Error(error),
+            }
+        };
+
+        // A step only runs out of bits if it needs more than the buffer holds, and all of them are
+        // part of the stream. Otherwise, the bytes that weren't used are handed back, so that the
+        // input after the end of the stream is never consumed.
+        let num_pulled = input.len() - remaining_input.len();
+        let num_unpulled = match code {
+            StatusCode::NeedsInput => 0,
+            _ => self.bits.unpull_whole_bytes(num_pulled),
+        };
+
+        Status {
+            num_read: num_pulled - num_unpulled,
+            num_written,
+            code,
+        }
+    }
+
+    // Decompresses until `max_unread` bytes are waiting to be read or the stream ends.
+    fn decompress(&mut self, input: &mut &[u8], max_unread: usize) -> Result<(), StepError> {
+        while self.window.num_unread() < max_unread {
+            match &mut self.state {
+                InflateState::BlockHeader => {
+                    let (bfinal, btype) = self.bits.step(input, |bits| {
+                        Ok((bits.read_bits(1)? != 0, bits.read_bits(2)?))
+                    })?;
+
+                    self.bfinal = bfinal;
+                    self.state = match btype {
+                        0b00 => InflateState::StoredBlockHeader,
+                        0b01 => InflateState::HuffmanBlock {
+                            literal_table: DecodeTable::new(
+                                &fixed_literal_table(),
+                                LITERAL_TABLE_BITS,
+                            ),
+                            distance_table: DecodeTable::new(
+                                &fixed_distance_table(),
+                                DISTANCE_TABLE_BITS,
+                            ),
+                        },
+                        0b10 => InflateState::DynamicBlockHeader,
+                        _ => return Err(DecodeError::InvalidBlockType.into()),
+                    };
+                }
+                InflateState::StoredBlockHeader => {
+                    let len = self.bits.step(input, read_stored_header)?;
+
+                    self.state = InflateState::StoredBlock {
+                        num_remaining: len as usize,
+                    };
+                }
+                InflateState::StoredBlock { num_remaining } => {
+                    if *num_remaining == 0 {
+                        self.state = self.next_block_state();
+                    } else if self.bits.length > 0 {
+                        // Use the bytes that were pulled into the bit buffer along with the header
+                        // first. The header is byte aligned, so only whole bytes are left.
+                        self.window.push(self.bits.read_bits(8)? as u8);
+                        *num_remaining -= 1;
+                    } else if input.is_empty() {
+                        return Err(StepError::NeedsInput);
+                    } else {
+                        let num_bytes = (*num_remaining)
+                            .min(input.len())
+                            .min(max_unread - self.window.num_unread());
+
+                        self.window.extend(&input[0..num_bytes]);
+                        *input = &input[num_bytes..];
+                        *num_remaining -= num_bytes;
+                    }
+                }
+                InflateState::DynamicBlockHeader => {
+                    let sizes = self.bits.step(input, read_table_sizes)?;
+
+                    self.state = InflateState::CodeLengthCodeLengths {
+                        sizes,
+                        lengths: [0; NUM_LENGTH_SYMBOLS],
+                        num_read: 0,
+                    };
+                }
+                InflateState::CodeLengthCodeLengths {
+                    sizes,
+                    lengths,
+                    num_read,
+                } => {
+                    let length = self
+                        .bits
+                        .step(input, |bits| bits.read_bits(CODE_LENGTH_CODE_LENGTH_LEN))?;
+
+                    lengths[LENGTH_ORDER[*num_read]] = length as u8;
+                    *num_read += 1;
+
+                    if *num_read == sizes.num_code_length_codes {
+                        self.state = InflateState::CodeLengths {
+                            sizes: *sizes,
+                            table: code_length_table(lengths)?,
+                            lengths: vec![0; sizes.num_literals + sizes.num_distances],
+                            num_read: 0,
+                        };
+                    }
+                }
+                InflateState::CodeLengths {
+                    sizes,
+                    table,
+                    lengths,
+                    num_read,
+                } => {
+                    // The code lengths of both alphabets form a single sequence, so repeats can
+                    // cross from the literal/length alphabet to the distance alphabet.
+                    let previous_length = num_read.checked_sub(1).map(|idx| lengths[idx]);
+                    let (length, num_repeated) = self
+                        .bits
+                        .step(input, |bits| read_code_length(bits, table, previous_length))?;
+
+                    *num_read = push_code_lengths(lengths, *num_read, length, num_repeated)?;
+
+                    if *num_read == lengths.len() {
+                        let (literal_table, distance_table) =
+                            finish_code_lengths(lengths, sizes.num_literals)?;
+
+                        self.state = InflateState::HuffmanBlock {
+                            literal_table: DecodeTable::new(&literal_table, LITERAL_TABLE_BITS),
+                            distance_table: DecodeTable::new(&distance_table, DISTANCE_TABLE_BITS),
+                        };
+                    }
+                }
+                InflateState::HuffmanBlock {
+                    literal_table,
+                    distance_table,
+                } => {
+                    let symbol = self.bits.step(input, |bits| {
+                        read_huffman_symbol(bits, literal_table, distance_table)
+                    })?;
+
+                    match symbol {
+                        Symbol::Literal(byte) => self.window.push(byte),
+                        Symbol::EndOfBlock => self.state = self.next_block_state(),
+                        Symbol::Match { length, distance } => {
+                            self.window.copy_match(length, distance)?
+                        }
+                    }
+                }
+                InflateState::Done => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn next_block_state(&self) -> InflateState {
+        if self.bfinal {
+            InflateState::Done
+        } else {
+            InflateState::BlockHeader
+        }
+    }
+}
+
+impl Default for Inflater {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BitBuffer {
+    // Pulls input bytes into the buffer, then runs a decoding step on a copy of the buffer and only
+    // keeps the result if the step completes. As no step needs more bits than the buffer holds
+    // after pulling, a step can only run out of bits if the input ran out.
+    fn step<T>(
+        &mut self,
+        input: &mut &[u8],
+        step: impl FnOnce(&mut BitBuffer) -> Result<T, StepError>,
+    ) -> Result<T, StepError> {
+        while self.length + 8 <= 64 {
+            let Some((&byte, rest)) = input.split_first() else {
+                break;
+            };
+
+            self.bits |= (byte as u64) << self.length;
+            self.length += 8;
+            *input = rest;
+        }
+
+        let mut bits = *self;
+        let result = step(&mut bits)?;
+        *self = bits;
+
+        Ok(result)
+    }
+
+    // Drops the whole bytes that were pulled into the buffer but not used yet, up to the
+    // `max_bytes` pulled by the current call, and returns how many there are. These are the last
+    // bytes in the buffer. The bits it held at the start of `inflate` are all part of the stream,
+    // and may still be there if no step completed since, so they are kept.
+    fn unpull_whole_bytes(&mut self, max_bytes: usize) -> usize {
+        let num_bytes = (self.length / 8).min(max_bytes);
+
+        self.length -= 8 * num_bytes;
+        if self.length < 64 {
+            self.bits &= (1 << self.length) - 1;
+        }
+
+        num_bytes
+    }
+
+    // The bits that were not consumed yet. The bits past `length` are zero.
+    fn peek_bits(&self) -> u64 {
+        self.bits
+    }
+
+    fn consume_bits(&mut self, length: usize) {
+        debug_assert!(length <= self.length);
+
+        self.bits >>= length;
+        self.length -= length;
+    }
+
+    fn read_bits(&mut self, length: usize) -> Result<u64, StepError> {
+        debug_assert!(length < 64);
+
+        if length > self.length {
+            return Err(StepError::NeedsInput);
+        }
+
+        let value = self.peek_bits() & ((1_u64 << length) - 1);
+        self.consume_bits(length);
+
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.consume_bits(self.length % 8);
+    }
+
+    fn read_symbol(&mut self, table: &DecodeTable) -> Result<usize, StepError> {
+        match table.lookup(self.peek_bits()) {
+            Some((symbol, length)) if length as usize <= self.length => {
+                self.consume_bits(length as usize);
+                Ok(symbol)
+            }
+            // The code may go on past the bits read so far.
+            _ if self.length < MAX_CODE_LENGTH => Err(StepError::NeedsInput),
+            _ => Err(DecodeError::InvalidCode.into()),
+        }
+    }
+}
+
+impl BlockReport {
+    pub fn average_match_length(&self) -> f64 {
+        self.total_match_length as f64 / self.num_matches.max(1) as f64
+    }
+
+    pub fn average_match_distance(&self) -> f64 {
+        self.total_match_distance as f64 / self.num_matches.max(1) as f64
+    }
+}
+
+impl<'a> BlockInspector<'a> {
+    /// Inspects the stream at the start of `data`. If `with_symbols` is set, every literal and
+    /// match is recorded along with the number of bits it took.
+    pub fn new(data: &'a [u8], with_symbols: bool) -> Self {
+        Self {
+            data,
+            input: data,
+            bits: BitBuffer::default(),
+            with_symbols,
+            done: false,
+        }
+    }
+
+    /// The number of bytes of the stream read so far. After the final block, this is the length of
+    /// the stream, and the data after it is left alone.
+    pub fn num_bytes_read(&self) -> usize {
+        self.data.len() - self.input.len() - self.bits.length / 8
+    }
+
+    /// The position in the stream that was reached, in bits.
+    pub fn bit_position(&self) -> u64 {
+        (self.data.len() - self.input.len()) as u64 * 8 - self.bits.length as u64
+    }
+
+    // Runs a decoding step. The whole stream is there, so running out of bits means it's truncated.
+    fn step<T>(
+        &mut self,
+        step: impl FnOnce(&mut BitBuffer) -> Result<T, StepError>,
+    ) -> Result<T, DecodeError> {
+        match self.bits.step(&mut self.input, step) {
+            Ok(value) => Ok(value),
+            Err(StepError::NeedsInput) => Err(DecodeError::TruncatedInput),
+            Err(StepError::Invalid(error)) => Err(error),
+        }
+    }
+
+    fn inspect_block(&mut self) -> Result<BlockInfo, DecodeError> {
+        let bit_offset = self.bit_position();
+        let (bfinal, btype) =
+            self.step(|bits| Ok((bits.read_bits(1)? != 0, bits.read_bits(2)?)))?;
+
+        let mut block = BlockInfo {
+            bit_offset,
+            bfinal,
+            kind: BlockKind::Stored,
+            header_bits: 0,
+            payload_bits: 0,
+            num_bytes: 0,
+            symbols: Vec::new(),
+        };
+
+        let (literal_table, distance_table) = match btype {
+            0b00 => {
+                self.inspect_stored_block(&mut block)?;
+                return Ok(block);
+            }
+            0b01 => {
+                block.kind = BlockKind::Fixed;
+                (fixed_literal_table(), fixed_distance_table())
+            }
+            0b10 => {
+                let (header, tables) = self.read_dynamic_header()?;
+
+                block.kind = BlockKind::Dynamic(header);
+                tables
+            }
+            _ => return Err(DecodeError::InvalidBlockType),
+        };
+
+        block.header_bits = self.bit_position() - bit_offset;
+
+        let literal_table = DecodeTable::new(&literal_table, LITERAL_TABLE_BITS);
+        let distance_table = DecodeTable::new(&distance_table, DISTANCE_TABLE_BITS);
+        loop {
+            let start = self.bit_position();
+            let symbol =
+                self.step(|bits| read_huffman_symbol(bits, &literal_table, &distance_table))?;
+
+            match symbol {
+                Symbol::Literal(_) => block.num_bytes += 1,
+                Symbol::Match { length, .. } => block.num_bytes += length as u64,
+                Symbol::EndOfBlock => {}
+            }
+
+            if self.with_symbols {
+                block.symbols.push(SymbolInfo {
+                    symbol,
+                    num_bits: self.bit_position() - start,
+                });
+            }
+
+            if symbol == Symbol::EndOfBlock {
+                break;
+            }
+        }
+
+        block.payload_bits = self.bit_position() - bit_offset - block.header_bits;
+        Ok(block)
+    }
+
+    fn inspect_stored_block(&mut self, block: &mut BlockInfo) -> Result<(), DecodeError> {
+        let len = self.step(read_stored_header)?;
+
+        block.header_bits = self.bit_position() - block.bit_offset;
+        block.payload_bits = len as u64 * 8;
+        block.num_bytes = len as u64;
+
+        // Skip the bytes that were pulled into the bit buffer first, then the rest of the input.
+        let num_buffered = (self.bits.length / 8).min(len as usize);
+        self.bits.consume_bits(num_buffered * 8);
+
+        let num_remaining = len as usize - num_buffered;
+        if num_remaining > self.input.len() {
+            return Err(DecodeError::TruncatedInput);
+        }
+        self.input = &self.input[num_remaining..];
+
+        Ok(())
+    }
+
+    // Reads the code lengths of a dynamic block, and returns them along with the tables they
+    // describe.
+    fn read_dynamic_header(
+        &mut self,
+    ) -> Result<(DynamicHeader, (HuffmanTable, HuffmanTable)), DecodeError> {
+        let sizes = self.step(read_table_sizes)?;
+
+        let mut code_length_lengths = [0; NUM_LENGTH_SYMBOLS];
+        for &symbol in &LENGTH_ORDER[0..sizes.num_code_length_codes] {
+            code_length_lengths[symbol] =
+                self.step(|bits| bits.read_bits(CODE_LENGTH_CODE_LENGTH_LEN))? as u8;
+        }
+        let table = code_length_table(&code_length_lengths)?;
+
+        let mut lengths = vec![0; sizes.num_literals + sizes.num_distances];
+        let mut num_read = 0;
+        let mut code_length_symbols = Vec::new();
+        while num_read < lengths.len() {
+            let start = self.bit_position();
+            let previous_length = num_read.checked_sub(1).map(|idx| lengths[idx]);
+            let (symbol, length, num_repeated) = self.step(|bits| {
+                // Only look at the symbol, `read_code_length` checks that it's valid.
+                let symbol = table
+                    .lookup(bits.peek_bits())
+                    .map_or(0, |(symbol, _)| symbol);
+                let (length, num_repeated) = read_code_length(bits, &table, previous_length)?;
+
+                Ok((symbol as u8, length, num_repeated))
+            })?;
+
+            num_read = push_code_lengths(&mut lengths, num_read, length, num_repeated)?;
+
+            code_length_symbols.push(CodeLengthSymbol {
+                symbol,
+                num_repeated,
+                num_bits: self.bit_position() - start,
+            });
+        }
+
+        let tables = finish_code_lengths(&lengths, sizes.num_literals)?;
+        let distance_lengths = lengths.split_off(sizes.num_literals);
+
+        let header = DynamicHeader {
+            num_literal_codes: sizes.num_literals,
+            num_distance_codes: sizes.num_distances,
+            num_code_length_codes: sizes.num_code_length_codes,
+            code_length_lengths,
+            code_length_symbols,
+            literal_lengths: lengths,
+            distance_lengths,
+        };
+
+        Ok((header, tables))
+    }
+}
+
+impl Iterator for BlockInspector<'_> {
+    type Item = Result<BlockInfo, DecodeError>;
+
+    // Stops after the final block, or after the first error.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = self.inspect_block();
+        self.done = result.as_ref().map_or(true, |block| block.bfinal);
+
+        Some(result)
+    }
+}
+
+// Writes the block using whichever block type is the cheapest, and adds it to the report.
+fn write_block<W: Write>(
+    writer: &mut BitWriter<W>,
+    block: &Block,
+    bfinal: bool,
+    report: Option<&mut CompressionReport>,
+) -> std::io::Result<()> {
+    let (stored_cost, fixed_cost, dynamic_cost, tables) =
+        block_costs(block, writer.num_buffered_bits());
+
+    if stored_cost <= fixed_cost.min(dynamic_cost) {
+        if let Some(report) = report {
+            report.blocks.push(stored_block_report(block, stored_cost));
+        }
+
+        write_stored_blocks(writer, &block.bytes, bfinal)
+    } else if fixed_cost <= dynamic_cost {
+        let fixed_literal_table = fixed_literal_table();
+        let fixed_distance_table = fixed_distance_table();
+
+        if let Some(report) = report {
+            report.blocks.push(block_report(
+                block,
+                BlockType::Fixed,
+                (3, fixed_cost),
+                &fixed_literal_table,
+                &fixed_distance_table,
+            ));
+        }
+
+        writer.write_bits((bfinal as u64) | 0b010, 3)?; // Write BFINAL and BTYPE
+
+        write_block_symbols(writer, block, &fixed_literal_table, &fixed_distance_table)
+    } else {
+        if let Some(report) = report {
+            report.blocks.push(block_report(
+                block,
+                BlockType::Dynamic,
+                (3 + huffman_tables_header_cost(&tables.header), dynamic_cost),
+                &tables.literal_table,
+                &tables.distance_table,
+            ));
+        }
+
+        writer.write_bits((bfinal as u64) | 0b100, 3)?; // Write BFINAL and BTYPE
+
+        write_huffman_tables(writer, &tables.header, &tables.info)?;
+        write_block_symbols(writer, block, &tables.literal_table, &tables.distance_table)
+    }
+}
+
+// Same as the choice made by `write_block`, assuming the block starts at a byte boundary.
+fn cheapest_block_cost(block: &Block) -> usize {
+    let (stored_cost, fixed_cost, dynamic_cost, _) = block_costs(block, 0);
+
+    stored_cost.min(fixed_cost).min(dynamic_cost)
+}
+
+// The size in bits of the block sent as stored, fixed and dynamic blocks, when the writer already
+// holds `num_buffered_bits` bits, along with the dynamic codes that were built for it.
+fn block_costs(block: &Block, num_buffered_bits: usize) -> (usize, usize, usize, DynamicTables) {
+    let info = block_compression_info(block);
+    let (literal_table, distance_table) = build_block_tables(block, &info);
+
+    let header = compress_huffman_tables_gen_header(&literal_table, &distance_table);
+
+    let dynamic_cost = 3
+        + huffman_tables_header_cost(&header)
+        + block_symbols_cost(block, &literal_table, &distance_table, &info);
+    let stored_cost = stored_blocks_cost(block.bytes.len(), num_buffered_bits);
+    let fixed_cost = 3 + block_symbols_cost(
+        block,
+        &fixed_literal_table(),
+        &fixed_distance_table(),
+        &info,
+    );
+
+    let tables = DynamicTables {
+        info,
+        literal_table,
+        distance_table,
+        header,
+    };
+
+    (stored_cost, fixed_cost, dynamic_cost, tables)
+}
+
+// Describes a block written with the given codes. The costs are the header bits and output bits.
+fn block_report(
+    block: &Block,
+    block_type: BlockType,
+    (header_bits, output_bits): (usize, usize),
+    literal_table: &HuffmanTable,
+    distance_table: &HuffmanTable,
+) -> BlockReport {
+    let mut report = BlockReport {
+        block_type,
+        input_bytes: block.bytes.len(),
+        output_bits,
+        header_bits,
+        num_literals: block.literal_freqs[0..EOF].iter().sum::<u32>() as usize,
+        num_matches: 0,
+        total_match_length: 0,
+        total_match_distance: 0,
+        literal_entropy: entropy(&block.literal_freqs),
+        literal_bits_per_symbol: bits_per_symbol(&block.literal_freqs, literal_table),
+        distance_entropy: entropy(&block.distance_freqs),
+        distance_bits_per_symbol: bits_per_symbol(&block.distance_freqs, distance_table),
+    };
+
+    // A match takes four symbols: the length symbol and its extra bits, then the same for the
+    // distance.
+    let mut i = 0;
+    while i < block.symbols.len() {
+        let symbol = block.symbols[i] as usize;
+
+        if symbol >= FIRST_LENGTH_SYMBOL {
+            let length = LENGTH_BASE[symbol - FIRST_LENGTH_SYMBOL] + block.symbols[i + 1];
+            let distance = DISTANCE_BASE[block.symbols[i + 2] as usize] + block.symbols[i + 3];
+
+            report.num_matches += 1;
+            report.total_match_length += length as usize;
+            report.total_match_distance += distance as usize;
+            i += 4;
+        } else {
+            i += 1;
+        }
+    }
+
+    report
+}
+
+// Describes a block sent as stored blocks, where every byte is a literal of 8 bits.
+fn stored_block_report(block: &Block, output_bits: usize) -> BlockReport {
+    let mut byte_freqs = [0; 256];
+    for &byte in &block.bytes {
+        byte_freqs[byte as usize] += 1;
+    }
+
+    BlockReport {
+        block_type: BlockType::Stored,
+        input_bytes: block.bytes.len(),
+        output_bits,
+        header_bits: output_bits - 8 * block.bytes.len(),
+        num_literals: block.bytes.len(),
+        num_matches: 0,
+        total_match_length: 0,
+        total_match_distance: 0,
+        literal_entropy: entropy(&byte_freqs),
+        literal_bits_per_symbol: 8.0,
+        distance_entropy: 0.0,
+        distance_bits_per_symbol: 0.0,
+    }
+}
+
+// The Shannon entropy of the symbols with these frequencies, in bits per symbol.
+fn entropy(freqs: &[u32]) -> f64 {
+    let total: u32 = freqs.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+
+    freqs
+        .iter()
+        .filter(|&&freq| freq != 0)
+        .map(|&freq| {
+            let probability = freq as f64 / total as f64;
+            probability * (1.0 / probability).log2()
+        })
+        .sum()
+}
+
+// The average code length of the symbols with these frequencies.
+fn bits_per_symbol(freqs: &[u32], table: &HuffmanTable) -> f64 {
+    let total: u32 = freqs.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+
+    let num_bits: u64 = freqs
+        .iter()
+        .enumerate()
+        .filter(|&(_, &freq)| freq != 0)
+        .map(|(symbol, &freq)| freq as u64 * table.code(symbol).length as u64)
+        .sum();
+
+    num_bits as f64 / total as f64
+}
+
+fn split_block(block: &Block) -> Vec<Block> {
+    // Split the symbols into literals and matches, keeping track of where each of them starts in
+    // the symbols and in the bytes.
+    let mut items = Vec::new();
+    let mut symbol_starts = Vec::new();
+    let mut byte_starts = Vec::new();
+
+    let mut i = 0;
+    let mut pos = 0;
+    while i < block.symbols.len() {
+        let symbol = block.symbols[i];
+
+        symbol_starts.push(i);
+        byte_starts.push(pos);
 
-    loop {
-        let bfinal = compress_block(&mut reader, &mut bit_writer, &mut block, &options)?;
+        if symbol as usize >= FIRST_LENGTH_SYMBOL {
+            let length_idx = symbol as usize - FIRST_LENGTH_SYMBOL;
 
-        if bfinal {
-            break;
+            items.push(BlockItem {
+                literal_symbol: symbol,
+                distance_symbol: Some(block.symbols[i + 2]),
+            });
+
+            pos += (LENGTH_BASE[length_idx] + block.symbols[i + 1]) as usize;
+            i += 4;
+        } else {
+            items.push(BlockItem {
+                literal_symbol: symbol,
+                distance_symbol: None,
+            });
+
+            pos += 1;
+            i += 1;
         }
     }
 
-    bit_writer.flush()
-}
+    symbol_starts.push(block.symbols.len());
+    byte_starts.push(block.bytes.len());
 
-pub fn decompress(reader: &mut (impl Read + Seek), writer: &mut impl Write) -> std::io::Result<()> {
-    let mut bit_reader = BitReader::new(reader);
-    let mut bit_writer = BitWriter::new(writer);
+    let sub_block = |range: std::ops::Range<usize>| {
+        let mut sub_block = Block::default();
 
-    loop {
-        let bfinal = decompress_block(&mut bit_reader, &mut bit_writer)?;
+        sub_block
+            .bytes
+            .extend_from_slice(&block.bytes[byte_starts[range.start]..byte_starts[range.end]]);
+        sub_block.symbols.extend_from_slice(
+            &block.symbols[symbol_starts[range.start]..symbol_starts[range.end]],
+        );
 
-        if bfinal {
-            break;
+        sub_block.literal_freqs[EOF] = 1;
+        for item in &items[range] {
+            sub_block.literal_freqs[item.literal_symbol as usize] += 1;
+            if let Some(distance_symbol) = item.distance_symbol {
+                sub_block.distance_freqs[distance_symbol as usize] += 1;
+            }
         }
-    }
 
-    bit_reader.put_back_extra()?;
-    bit_writer.flush()
-}
+        sub_block
+    };
 
-fn is_end_of_file(reader: &mut (impl Read + Seek)) -> std::io::Result<bool> {
-    let mut buf = [0; 1];
-    // Check end of file
-    // Not the best way to check end of file I guess.
-    let read_bytes = reader.read(&mut buf)?;
-    if read_bytes == 1 {
-        // reader.seek_relative(-1)?; // Not stabilized yet
-        reader.seek(std::io::SeekFrom::Current(-1))?;
-        Ok(false)
-    } else {
-        Ok(true)
+    let split_points = find_split_points(&items, MIN_SPLIT_BLOCK_LEN, |range| {
+        cheapest_block_cost(&sub_block(range))
+    });
+
+    let mut blocks = Vec::with_capacity(split_points.len() + 1);
+    let mut start = 0;
+    for end in split_points.into_iter().chain([items.len()]) {
+        blocks.push(sub_block(start..end));
+        start = end;
     }
-}
 
-fn compress_block<W: Write>(
-    reader: &mut (impl Read + Seek),
-    writer: &mut BitWriter<W>,
-    block: &mut Block,
-    options: &DeflateOptions,
-) -> std::io::Result<bool> {
-    let info = compress_block_gen_symbols(reader, block, options)?;
+    blocks
+}
 
+fn build_block_tables(block: &Block, info: &BlockCompressionInfo) -> (HuffmanTable, HuffmanTable) {
     let literal_table = HuffmanTable::build_length_limited(
         &block.literal_freqs[0..info.num_literal_codes],
         MAX_CODE_LENGTH,
@@ -124,112 +1472,516 @@ fn compress_block<W: Write>(
     )
     .unwrap();
 
-    let bfinal = is_end_of_file(reader)?;
-    writer.write_bits((bfinal as u64) | 0b100, 3)?; // Write BFINAL and BTYPE
+    (literal_table, distance_table)
+}
 
-    write_huffman_tables(writer, &literal_table, &distance_table, &info)?;
+fn write_block_symbols<W: Write>(
+    writer: &mut BitWriter<W>,
+    block: &Block,
+    literal_table: &HuffmanTable,
+    distance_table: &HuffmanTable,
+) -> std::io::Result<()> {
+    let mut i = 0;
+    while i < block.symbols.len() {
+        let symbol = block.symbols[i] as usize;
 
-    for symbol in &block.symbols {
-        let code = &literal_table.code(*symbol as usize);
+        let code = literal_table.code(symbol);
         writer.write_bits(code.code.into(), code.length.into())?;
+
+        if symbol >= FIRST_LENGTH_SYMBOL {
+            // Length extra bits, distance symbol and distance extra bits follow a length symbol.
+            let length_extra = block.symbols[i + 1];
+            let distance_symbol = block.symbols[i + 2] as usize;
+            let distance_extra = block.symbols[i + 3];
+
+            let length_extra_bits = LENGTH_EXTRA_BITS[symbol - FIRST_LENGTH_SYMBOL];
+            writer.write_bits(length_extra.into(), length_extra_bits.into())?;
+
+            let code = distance_table.code(distance_symbol);
+            writer.write_bits(code.code.into(), code.length.into())?;
+
+            let distance_extra_bits = DISTANCE_EXTRA_BITS[distance_symbol];
+            writer.write_bits(distance_extra.into(), distance_extra_bits.into())?;
+
+            i += 3;
+        }
+
+        i += 1;
     }
 
     // Write EOF
     let eof_symbol = literal_table.code(EOF);
-    writer.write_bits(eof_symbol.code.into(), eof_symbol.length.into())?;
-
-    Ok(bfinal)
+    writer.write_bits(eof_symbol.code.into(), eof_symbol.length.into())
 }
 
-fn decompress_block<R: Read + Seek, W: Write>(
-    reader: &mut BitReader<R>,
+fn write_stored_blocks<W: Write>(
     writer: &mut BitWriter<W>,
-) -> std::io::Result<bool> {
-    // Read BFINAL and BTYPE
-    let bfinal = reader.read_bits(1)?;
-    let btype = reader.read_bits(2)?;
+    bytes: &[u8],
+    bfinal: bool,
+) -> std::io::Result<()> {
+    // A stored block can hold at most 65535 bytes, so larger blocks are split. An empty block
+    // still needs a single stored block.
+    let num_blocks = bytes.len().div_ceil(MAX_STORED_BLOCK_SIZE).max(1);
+
+    for idx in 0..num_blocks {
+        let start = idx * MAX_STORED_BLOCK_SIZE;
+        let chunk = &bytes[start..(start + MAX_STORED_BLOCK_SIZE).min(bytes.len())];
+        let is_last = idx + 1 == num_blocks;
+
+        writer.write_bits((bfinal && is_last) as u64, 3)?; // Write BFINAL and BTYPE
+        writer.align_to_byte()?;
+
+        let len = chunk.len() as u16;
+        writer.write_all(&len.to_le_bytes())?;
+        writer.write_all(&(!len).to_le_bytes())?;
+        writer.write_all(chunk)?;
+    }
 
-    assert!(btype == 0b10);
+    Ok(())
+}
 
-    let table = read_huffman_table(reader)?;
-    let tree = HuffmanTree::from(&table);
+fn stored_blocks_cost(num_bytes: usize, num_buffered_bits: usize) -> usize {
+    let num_blocks = num_bytes.div_ceil(MAX_STORED_BLOCK_SIZE).max(1);
+    // The first block pads to the byte boundary from wherever the previous block ended, while the
+    // following ones always start at a byte boundary and pad 5 bits after their 3 header bits.
+    let first_padding = (8 - (num_buffered_bits + 3) % 8) % 8;
+    let first_block_cost = 3 + first_padding + 32;
+    let other_blocks_cost = (num_blocks - 1) * (3 + 5 + 32);
 
-    let mut iter = tree.create_walk_iter();
+    first_block_cost + other_blocks_cost + 8 * num_bytes
+}
 
-    loop {
-        while !iter.leaf {
-            let bit = reader.read_bits(1)? != 0;
-            iter = tree.walk(iter, bit).unwrap();
+fn block_symbols_cost(
+    block: &Block,
+    literal_table: &HuffmanTable,
+    distance_table: &HuffmanTable,
+    info: &BlockCompressionInfo,
+) -> usize {
+    let mut cost = 0;
+
+    for (symbol, freq) in block.literal_freqs[0..info.num_literal_codes]
+        .iter()
+        .enumerate()
+    {
+        let mut symbol_cost = literal_table.code(symbol).length as usize;
+        if symbol >= FIRST_LENGTH_SYMBOL {
+            symbol_cost += LENGTH_EXTRA_BITS[symbol - FIRST_LENGTH_SYMBOL] as usize;
         }
 
-        let symbol = iter.idx;
+        cost += *freq as usize * symbol_cost;
+    }
+
+    for (symbol, freq) in block.distance_freqs[0..info.num_distance_codes]
+        .iter()
+        .enumerate()
+    {
+        let symbol_cost =
+            distance_table.code(symbol).length as usize + DISTANCE_EXTRA_BITS[symbol] as usize;
 
-        if symbol == EOF {
-            break;
+        cost += *freq as usize * symbol_cost;
+    }
+
+    cost
+}
+
+fn read_huffman_symbol(
+    bits: &mut BitBuffer,
+    literal_table: &DecodeTable,
+    distance_table: &DecodeTable,
+) -> Result<Symbol, StepError> {
+    let symbol = bits.read_symbol(literal_table)?;
+
+    if symbol < EOF {
+        return Ok(Symbol::Literal(symbol as u8));
+    } else if symbol == EOF {
+        return Ok(Symbol::EndOfBlock);
+    }
+
+    let length_idx = symbol - FIRST_LENGTH_SYMBOL;
+    if length_idx >= LENGTH_BASE.len() {
+        return Err(DecodeError::InvalidLengthSymbol.into());
+    }
+    let length = LENGTH_BASE[length_idx] as usize
+        + bits.read_bits(LENGTH_EXTRA_BITS[length_idx].into())? as usize;
+
+    let distance_idx = bits.read_symbol(distance_table)?;
+    if distance_idx >= NUM_DISTANCE_SYMBOLS {
+        return Err(DecodeError::InvalidDistanceSymbol.into());
+    }
+    let distance = DISTANCE_BASE[distance_idx] as usize
+        + bits.read_bits(DISTANCE_EXTRA_BITS[distance_idx].into())? as usize;
+
+    Ok(Symbol::Match { length, distance })
+}
+
+// Checks that the code lengths describe a valid prefix code, which is needed for building its
+// decode table. Like zlib, incomplete codes are only allowed for a single code of length 1 (or no codes at
+// all) if `allow_single_code` is set, as encoders can't do better with a single symbol.
+fn check_code_lengths(lengths: &[u8], allow_single_code: bool) -> Result<(), DecodeError> {
+    let mut lengths_count = [0; MAX_CODE_LENGTH + 1];
+    for &length in lengths {
+        lengths_count[length as usize] += 1;
+    }
+
+    // The number of codes of the current length that are still available.
+    let mut num_left: i32 = 1;
+    for &count in &lengths_count[1..] {
+        num_left = 2 * num_left - count;
+        if num_left < 0 {
+            return Err(DecodeError::OversubscribedCode);
         }
+    }
+
+    let num_codes = lengths.len() - lengths_count[0] as usize;
+    let is_single_code = num_codes <= 1 && lengths_count[2..].iter().all(|&count| count == 0);
 
-        writer.write(&symbol.to_le_bytes()[0..1])?;
-        iter = tree.create_walk_iter();
+    if num_left > 0 && !(allow_single_code && is_single_code) {
+        return Err(DecodeError::IncompleteCode);
     }
 
-    Ok(bfinal != 0)
+    Ok(())
+}
+
+// Reads LEN and NLEN after skipping to the byte boundary, and returns the length of the stored block.
+fn read_stored_header(bits: &mut BitBuffer) -> Result<u16, StepError> {
+    bits.align_to_byte();
+    let len = bits.read_bits(16)? as u16;
+    let nlen = bits.read_bits(16)? as u16;
+
+    if len != !nlen {
+        return Err(DecodeError::StoredLengthMismatch.into());
+    }
+
+    Ok(len)
+}
+
+// Reads HLIT, HDIST and HCLEN at the start of a dynamic block.
+fn read_table_sizes(bits: &mut BitBuffer) -> Result<TableSizes, StepError> {
+    let sizes = TableSizes {
+        num_literals: bits.read_bits(5)? as usize + 257, // HLIT
+        num_distances: bits.read_bits(5)? as usize + 1,  // HDIST
+        num_code_length_codes: bits.read_bits(4)? as usize + 4, // HCLEN
+    };
+
+    if sizes.num_literals > NUM_LITERAL_SYMBOLS || sizes.num_distances > NUM_DISTANCE_SYMBOLS {
+        return Err(DecodeError::TooManySymbols.into());
+    }
+
+    Ok(sizes)
+}
+
+// Builds the decode table of the code length alphabet once its lengths were read.
+fn code_length_table(lengths: &[u8; NUM_LENGTH_SYMBOLS]) -> Result<DecodeTable, DecodeError> {
+    check_code_lengths(lengths, false)?;
+
+    Ok(DecodeTable::new(
+        &HuffmanTable::from_lengths(lengths),
+        MAX_LENGTH_CODE_LENGTH,
+    ))
+}
+
+// Sets the code length that was read after the `num_read` lengths that are already known, as many
+// times as it's repeated, and returns the new number of known lengths.
+fn push_code_lengths(
+    lengths: &mut [u8],
+    num_read: usize,
+    length: u8,
+    num_repeated: usize,
+) -> Result<usize, DecodeError> {
+    if num_read + num_repeated > lengths.len() {
+        return Err(DecodeError::TooManyCodeLengths);
+    }
+
+    lengths[num_read..num_read + num_repeated].fill(length);
+    Ok(num_read + num_repeated)
+}
+
+// Checks the code lengths of both alphabets once all of them were read, and returns the
+// literal/length and distance tables.
+fn finish_code_lengths(
+    lengths: &[u8],
+    num_literals: usize,
+) -> Result<(HuffmanTable, HuffmanTable), DecodeError> {
+    if lengths[EOF] == 0 {
+        return Err(DecodeError::MissingEndOfBlock);
+    }
+
+    let (literal_lengths, distance_lengths) = lengths.split_at(num_literals);
+    check_code_lengths(literal_lengths, true)?;
+    check_code_lengths(distance_lengths, true)?;
+
+    Ok((
+        HuffmanTable::from_lengths(literal_lengths),
+        HuffmanTable::from_lengths(distance_lengths),
+    ))
+}
+
+// Reads a code length symbol along with its extra bits, and returns the code length and the number
+// of times it's repeated.
+fn read_code_length(
+    bits: &mut BitBuffer,
+    table: &DecodeTable,
+    previous_length: Option<u8>,
+) -> Result<(u8, usize), StepError> {
+    let symbol = bits.read_symbol(table)? as u16;
+
+    match symbol {
+        0..=15 => Ok((symbol as u8, 1)),
+        REPEAT_PREV_3_6_SYMBOL => {
+            let length = previous_length.ok_or(DecodeError::RepeatWithoutPreviousLength)?;
+            let num_repeated = bits.read_bits(REPEAT_PREV_3_6_ARG_LEN)? as usize + 3;
+
+            Ok((length, num_repeated))
+        }
+        REPEAT_0_CODELEN_3_10_SYMBOL => {
+            let num_repeated = bits.read_bits(REPEAT_0_CODELEN_3_10_ARG_LEN)? as usize + 3;
+
+            Ok((0, num_repeated))
+        }
+        REPEAT_0_CODELEN_11_138_SYMBOL => {
+            let num_repeated = bits.read_bits(REPEAT_0_CODELEN_11_138_ARG_LEN)? as usize + 11;
+
+            Ok((0, num_repeated))
+        }
+        _ => Err(DecodeError::InvalidCode.into()),
+    }
 }
 
 fn compress_block_gen_symbols(
-    bit_reader: &mut impl Read,
+    bytes: &[u8],
+    match_finder: &mut MatchFinder,
     block: &mut Block,
-    options: &DeflateOptions,
-) -> std::io::Result<BlockCompressionInfo> {
-    // Reset block
+    config: &LevelConfig,
+) {
+    reset_block_symbols(block);
+    block.bytes.clear();
+    block.bytes.extend_from_slice(bytes);
+
+    if config.strategy != ParseStrategy::Store {
+        match_finder.push(bytes);
+    }
+
+    match config.strategy {
+        ParseStrategy::Store => {}
+        ParseStrategy::Greedy => gen_symbols_greedy(match_finder, block, config),
+        ParseStrategy::Lazy => gen_symbols_lazy(match_finder, block, config, 1),
+        ParseStrategy::Lazy2 => gen_symbols_lazy(match_finder, block, config, 2),
+        ParseStrategy::Optimal => gen_symbols_optimal(match_finder, block),
+    }
+}
+
+fn reset_block_symbols(block: &mut Block) {
     block.symbols.clear();
     block.literal_freqs.fill(0);
     block.distance_freqs.fill(0);
 
     // Single EOF symbol at the last of the block.
     block.literal_freqs[EOF] = 1;
+}
 
-    let mut buffer = [0; 256];
-    let mut tot_read_bytes = 0;
-    let mut bytes_to_read = buffer.len().min(options.block_size);
+fn block_compression_info(block: &Block) -> BlockCompressionInfo {
+    // Only send the codes up to the last used one.
+    let num_literal_codes = block
+        .literal_freqs
+        .iter()
+        .rposition(|&freq| freq != 0)
+        .map_or(0, |idx| idx + 1)
+        .max(FIRST_LENGTH_SYMBOL);
+    let num_distance_codes = block
+        .distance_freqs
+        .iter()
+        .rposition(|&freq| freq != 0)
+        .map_or(0, |idx| idx + 1)
+        .max(1);
+
+    BlockCompressionInfo {
+        num_literal_codes,
+        num_distance_codes,
+    }
+}
 
-    // TODO: Implement LZ77
-    loop {
-        let num_read_bytes = bit_reader.read(&mut buffer[0..bytes_to_read])?;
+fn gen_symbols_greedy(match_finder: &mut MatchFinder, block: &mut Block, config: &LevelConfig) {
+    while match_finder.lookahead() > 0 {
+        if let Some(m) = match_finder.find_match(MAX_MATCH) {
+            push_match(block, m.length, m.distance);
 
-        for byte in &buffer[0..num_read_bytes] {
-            block.symbols.push(*byte as u16);
-            block.literal_freqs[*byte as usize] += 1;
+            if m.length as usize >= config.lazy_length {
+                match_finder.skip(m.length as usize);
+            } else {
+                match_finder.advance(m.length as usize);
+            }
+        } else {
+            push_literal(block, match_finder.pending()[0]);
+            match_finder.advance(1);
         }
+    }
+}
 
-        tot_read_bytes += num_read_bytes;
-        let remaining_bytes = options.block_size - tot_read_bytes;
+// Before taking a match, looks for longer matches up to `max_lookahead` positions ahead. If one is
+// found, the bytes before it are emitted as literals instead, and it becomes the match to beat.
+fn gen_symbols_lazy(
+    match_finder: &mut MatchFinder,
+    block: &mut Block,
+    config: &LevelConfig,
+    max_lookahead: usize,
+) {
+    // Matches are at least 3 bytes long, so looking ahead never goes past the end of the match.
+    debug_assert!(max_lookahead < MIN_MATCH);
+
+    // A longer match found while looking ahead, starting at the current position.
+    let mut next_match: Option<Match> = None;
+
+    while match_finder.lookahead() > 0 {
+        let m = match next_match
+            .take()
+            .or_else(|| match_finder.find_match(MAX_MATCH))
+        {
+            Some(m) => m,
+            None => {
+                push_literal(block, match_finder.pending()[0]);
+                match_finder.advance(1);
+                continue;
+            }
+        };
 
-        if num_read_bytes == 0 || remaining_bytes <= 0 {
-            break;
+        let mut skipped_bytes = [0; MIN_MATCH];
+        skipped_bytes.copy_from_slice(&match_finder.pending()[0..MIN_MATCH]);
+
+        let mut num_skipped = 0;
+
+        if (m.length as usize) < config.lazy_length {
+            while num_skipped < max_lookahead {
+                match_finder.advance(1);
+                num_skipped += 1;
+
+                next_match = match_finder.find_longer_match(MAX_MATCH, m.length.into());
+                if next_match.is_some() {
+                    break;
+                }
+            }
         }
 
-        bytes_to_read = buffer.len().min(remaining_bytes);
+        if next_match.is_some() {
+            for byte in &skipped_bytes[0..num_skipped] {
+                push_literal(block, *byte);
+            }
+        } else {
+            push_match(block, m.length, m.distance);
+            match_finder.advance(m.length as usize - num_skipped);
+        }
     }
+}
+
+fn gen_symbols_optimal(match_finder: &mut MatchFinder, block: &mut Block) {
+    let matches = BlockMatches::collect(match_finder, match_finder.lookahead());
+
+    // Start from the fixed codes, as there's no previous parse yet.
+    let mut costs = cost_model_from_tables(&fixed_literal_table(), &fixed_distance_table());
+
+    let mut best_cost = usize::MAX;
+    let mut best_symbols = Vec::new();
+    let mut best_literal_freqs = block.literal_freqs;
+    let mut best_distance_freqs = block.distance_freqs;
+
+    for _ in 0..MAX_OPTIMAL_PARSE_ITERATIONS {
+        let steps = optimal_parse(&block.bytes, &matches, &costs);
+
+        reset_block_symbols(block);
+
+        let mut pos = 0;
+        for step in steps {
+            if let Some(m) = step {
+                push_match(block, m.length, m.distance);
+                pos += m.length as usize;
+            } else {
+                let byte = block.bytes[pos];
+                push_literal(block, byte);
+                pos += 1;
+            }
+        }
+
+        let info = block_compression_info(block);
+        let (literal_table, distance_table) = build_block_tables(block, &info);
+        let header = compress_huffman_tables_gen_header(&literal_table, &distance_table);
+
+        let cost = huffman_tables_header_cost(&header)
+            + block_symbols_cost(block, &literal_table, &distance_table, &info);
+
+        if cost >= best_cost {
+            break;
+        }
 
-    assert!(tot_read_bytes > 0);
+        best_cost = cost;
+        best_symbols.clone_from(&block.symbols);
+        best_literal_freqs = block.literal_freqs;
+        best_distance_freqs = block.distance_freqs;
+
+        costs = cost_model_from_tables(&literal_table, &distance_table);
+    }
 
-    Ok(BlockCompressionInfo {
-        num_literal_codes: 257,
-        num_distance_codes: 1,
-    })
+    block.symbols = best_symbols;
+    block.literal_freqs = best_literal_freqs;
+    block.distance_freqs = best_distance_freqs;
 }
 
-fn write_huffman_tables<W: Write>(
-    writer: &mut BitWriter<W>,
+fn cost_model_from_tables(
     literal_table: &HuffmanTable,
     distance_table: &HuffmanTable,
-    info: &BlockCompressionInfo,
-) -> std::io::Result<()> {
-    // Write HLIT (number of literals - 257)
-    writer.write_bits((info.num_literal_codes - 257) as u64, 5)?;
-    // Write HDIST (number of distant codes - 1)
-    writer.write_bits((info.num_distance_codes - 1) as u64, 5)?;
+) -> CostModel {
+    // Symbols that are not used in the tables have no code, but they could be used in the next
+    // parse, so assume they would get the longest code.
+    let code_length = |table: &HuffmanTable, symbol: usize| match table.codes.get(symbol) {
+        Some(code) if code.length > 0 => code.length as u32,
+        _ => MAX_CODE_LENGTH as u32,
+    };
+
+    let mut costs = CostModel::new();
+
+    for (byte, cost) in costs.literal_costs.iter_mut().enumerate() {
+        *cost = code_length(literal_table, byte);
+    }
+
+    for (length, cost) in costs.length_costs.iter_mut().enumerate().skip(MIN_MATCH) {
+        let length_idx = LENGTH_BASE.partition_point(|&base| base as usize <= length) - 1;
+
+        *cost = code_length(literal_table, FIRST_LENGTH_SYMBOL + length_idx)
+            + LENGTH_EXTRA_BITS[length_idx] as u32;
+    }
+
+    for (distance, cost) in costs.distance_costs.iter_mut().enumerate().skip(1) {
+        let distance_idx = DISTANCE_BASE.partition_point(|&base| base as usize <= distance) - 1;
+
+        *cost =
+            code_length(distance_table, distance_idx) + DISTANCE_EXTRA_BITS[distance_idx] as u32;
+    }
+
+    costs
+}
+
+fn push_literal(block: &mut Block, byte: u8) {
+    block.symbols.push(byte as u16);
+    block.literal_freqs[byte as usize] += 1;
+}
+
+fn push_match(block: &mut Block, length: u16, distance: u16) {
+    let length_idx = LENGTH_BASE.partition_point(|&base| base <= length) - 1;
+    let distance_idx = DISTANCE_BASE.partition_point(|&base| base <= distance) - 1;
+
+    block
+        .symbols
+        .push((FIRST_LENGTH_SYMBOL + length_idx) as u16);
+    block.symbols.push(length - LENGTH_BASE[length_idx]);
+    block.symbols.push(distance_idx as u16);
+    block.symbols.push(distance - DISTANCE_BASE[distance_idx]);
 
+    block.literal_freqs[FIRST_LENGTH_SYMBOL + length_idx] += 1;
+    block.distance_freqs[distance_idx] += 1;
+}
+
+fn compress_huffman_tables_gen_header(
+    literal_table: &HuffmanTable,
+    distance_table: &HuffmanTable,
+) -> HuffmanTablesHeader {
     let mut lengths_freqs: [u32; NUM_LENGTH_SYMBOLS] = [0; NUM_LENGTH_SYMBOLS];
 
     let literal_table_lengths_symbols =
@@ -248,27 +2000,81 @@ fn write_huffman_tables<W: Write>(
         result
     };
 
-    // Write HCLEN (number of code length codes - 4)
-    writer.write_bits((num_code_length_codes - 4) as u64, 4)?;
-
     let length_table =
         HuffmanTable::build_length_limited(&lengths_freqs, MAX_LENGTH_CODE_LENGTH).unwrap();
 
+    HuffmanTablesHeader {
+        num_code_length_codes,
+        length_table,
+        literal_table_lengths_symbols,
+        distance_table_lengths_symbols,
+    }
+}
+
+fn huffman_tables_header_cost(header: &HuffmanTablesHeader) -> usize {
+    // HLIT, HDIST, HCLEN, and the code lengths for the code lengths alphabet.
+    let mut cost = 5 + 5 + 4 + header.num_code_length_codes * CODE_LENGTH_CODE_LENGTH_LEN;
+
+    for symbols in [
+        &header.literal_table_lengths_symbols,
+        &header.distance_table_lengths_symbols,
+    ] {
+        let mut i = 0;
+        while i < symbols.len() {
+            let symbol = symbols[i];
+            cost += header.length_table.code(symbol as usize).length as usize;
+
+            let arg_len = match symbol {
+                REPEAT_PREV_3_6_SYMBOL => REPEAT_PREV_3_6_ARG_LEN,
+                REPEAT_0_CODELEN_3_10_SYMBOL => REPEAT_0_CODELEN_3_10_ARG_LEN,
+                REPEAT_0_CODELEN_11_138_SYMBOL => REPEAT_0_CODELEN_11_138_ARG_LEN,
+                _ => 0,
+            };
+            if arg_len > 0 {
+                cost += arg_len;
+                i += 1;
+            }
+
+            i += 1;
+        }
+    }
+
+    cost
+}
+
+fn write_huffman_tables<W: Write>(
+    writer: &mut BitWriter<W>,
+    header: &HuffmanTablesHeader,
+    info: &BlockCompressionInfo,
+) -> std::io::Result<()> {
+    // Write HLIT (number of literals - 257)
+    writer.write_bits((info.num_literal_codes - 257) as u64, 5)?;
+    // Write HDIST (number of distant codes - 1)
+    writer.write_bits((info.num_distance_codes - 1) as u64, 5)?;
+    // Write HCLEN (number of code length codes - 4)
+    writer.write_bits((header.num_code_length_codes - 4) as u64, 4)?;
+
     // Write code lengths for the code lengths alphabet
-    for idx in 0..num_code_length_codes {
+    for symbol in &LENGTH_ORDER[0..header.num_code_length_codes] {
         writer.write_bits(
-            length_table.code(LENGTH_ORDER[idx]).length as u64,
+            header.length_table.code(*symbol).length as u64,
             CODE_LENGTH_CODE_LENGTH_LEN,
         )?;
     }
 
-    // print_header_symbols(&literal_table_lengths_symbols, &length_table);
-
     // Write code lengths for the literal/length alphabet.
-    write_huffman_length_symbols(writer, &literal_table_lengths_symbols, &length_table)?;
+    write_huffman_length_symbols(
+        writer,
+        &header.literal_table_lengths_symbols,
+        &header.length_table,
+    )?;
 
     // Write code lengths for the distance alphabet.
-    write_huffman_length_symbols(writer, &distance_table_lengths_symbols, &length_table)?;
+    write_huffman_length_symbols(
+        writer,
+        &header.distance_table_lengths_symbols,
+        &header.length_table,
+    )?;
 
     Ok(())
 }
@@ -285,8 +2091,7 @@ fn compress_huffman_table_gen_symbols(
         return [0].into();
     }
 
-    let mut symbols = Vec::<u16>::new();
-    symbols.reserve(table.codes.len());
+    let mut symbols = Vec::<u16>::with_capacity(table.codes.len());
 
     let mut i: usize = 0;
     while i < table.codes.len() {
@@ -307,13 +2112,13 @@ fn compress_huffman_table_gen_symbols(
                 lengths_freqs[REPEAT_0_CODELEN_3_10_SYMBOL as usize] += 1;
 
                 num_repeated = num_repeated.min(10);
-                symbols.push(REPEAT_0_CODELEN_3_10_SYMBOL as u16);
+                symbols.push(REPEAT_0_CODELEN_3_10_SYMBOL);
                 symbols.push((num_repeated - 3) as u16);
             } else {
                 lengths_freqs[REPEAT_0_CODELEN_11_138_SYMBOL as usize] += 1;
 
                 num_repeated = num_repeated.min(138);
-                symbols.push(REPEAT_0_CODELEN_11_138_SYMBOL as u16);
+                symbols.push(REPEAT_0_CODELEN_11_138_SYMBOL);
                 symbols.push((num_repeated - 11) as u16);
             }
         } else {
@@ -325,7 +2130,7 @@ fn compress_huffman_table_gen_symbols(
                 lengths_freqs[REPEAT_PREV_3_6_SYMBOL as usize] += 1;
 
                 num_repeated = num_repeated.min(7);
-                symbols.push(REPEAT_PREV_3_6_SYMBOL as u16);
+                symbols.push(REPEAT_PREV_3_6_SYMBOL);
                 symbols.push((num_repeated - 4) as u16);
             } else {
                 num_repeated = 1;
@@ -341,7 +2146,7 @@ fn compress_huffman_table_gen_symbols(
 
 fn write_huffman_length_symbols<W: Write>(
     writer: &mut BitWriter<W>,
-    symbols: &Vec<u16>,
+    symbols: &[u16],
     length_table: &HuffmanTable,
 ) -> std::io::Result<()> {
     // Write code lengths for the literal/length alphabet.
@@ -374,134 +2179,343 @@ fn write_huffman_length_symbols<W: Write>(
     Ok(())
 }
 
-fn read_huffman_table<R: Read>(reader: &mut BitReader<R>) -> std::io::Result<HuffmanTable> {
-    let num_literals = (reader.read_bits(5)? + 257) as usize; // HLIT
-    let num_distance_codes = (reader.read_bits(5)? + 1) as usize; // HDIST
-    let num_code_length_codes = (reader.read_bits(4)? + 4) as usize; // HCLEN
+// The fixed codes from RFC1951 section 3.2.6.
+fn fixed_literal_table() -> HuffmanTable {
+    let mut lengths = [0; NUM_FIXED_LITERAL_SYMBOLS];
 
-    let mut lengths = [0; NUM_LITERAL_SYMBOLS];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
 
-    // Read the table for the alphabet lengths.
-    for idx in 0..num_code_length_codes {
-        lengths[LENGTH_ORDER[idx as usize]] = reader.read_bits(3)? as u8;
-    }
+    HuffmanTable::from_lengths(&lengths)
+}
 
-    let length_table = HuffmanTable::from_lengths(&lengths);
-    let length_huffman_tree = HuffmanTree::from(&length_table);
+fn fixed_distance_table() -> HuffmanTable {
+    HuffmanTable::from_lengths(&[5; NUM_FIXED_DISTANCE_SYMBOLS])
+}
 
-    // let mut symbols = Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
 
-    // Read the table for the alphabet.
-    let mut literal_idx = 0;
-    while literal_idx < num_literals {
-        let mut iter = length_huffman_tree.create_walk_iter();
+    // Text with long repeats, followed by noise that only stored blocks can hold.
+    fn sample_data() -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..2000 {
+            data.extend_from_slice(format!("line {} of the sample, ", i % 37).as_bytes());
+        }
 
-        while !iter.leaf {
-            let bit = reader.read_bits(1)? != 0;
-            iter = length_huffman_tree.walk(iter, bit).unwrap();
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        for _ in 0..40_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            data.push(state as u8);
         }
-        let code_length = iter.idx as u16;
 
-        // symbols.push(code_length);
+        data
+    }
+
+    fn compressed(data: &[u8], options: DeflateOptions) -> Vec<u8> {
+        let mut stream = Vec::new();
+        compress(&mut Cursor::new(data), &mut stream, options).unwrap();
+        stream
+    }
 
-        match code_length {
-            0..=15 => {
-                lengths[literal_idx] = code_length as u8;
-                literal_idx += 1;
+    // Inflates the input handed over in chunks of `input_chunk` bytes, with an output buffer of
+    // `output_chunk` bytes. Returns the output and the number of input bytes consumed. Every time
+    // more input is needed, also checks that a call with no room for output takes no input.
+    fn inflate_in_chunks(
+        input: &[u8],
+        input_chunk: usize,
+        output_chunk: usize,
+    ) -> Result<(Vec<u8>, usize), DecodeError> {
+        let mut inflater = Inflater::new();
+        let mut output = Vec::new();
+        let mut buffer = vec![0; output_chunk];
+        let mut position = 0;
+
+        loop {
+            let chunk = &input[position..input.len().min(position + input_chunk)];
+            let status = inflater.inflate(chunk, &mut buffer);
+
+            assert!(status.num_read <= chunk.len());
+            output.extend_from_slice(&buffer[..status.num_written]);
+            position += status.num_read;
+
+            match status.code {
+                StatusCode::NeedsInput => {
+                    assert_eq!(status.num_read, chunk.len());
+                    if position == input.len() {
+                        return Err(DecodeError::TruncatedInput);
+                    }
+
+                    let status = inflater.inflate(&input[position..], &mut []);
+                    assert_eq!((status.num_read, status.num_written), (0, 0));
+                }
+                StatusCode::NeedsOutput => assert_eq!(status.num_written, output_chunk),
+                StatusCode::StreamEnd => return Ok((output, position)),
+                StatusCode::Error(error) => return Err(error),
             }
-            REPEAT_PREV_3_6_SYMBOL => {
-                let num_repeated = (reader.read_bits(REPEAT_PREV_3_6_ARG_LEN)? + 3) as usize;
-                let prev_length = lengths[literal_idx - 1];
+        }
+    }
 
-                lengths[literal_idx..literal_idx + num_repeated].fill(prev_length as u8);
-                literal_idx += num_repeated;
+    // Checks that the stream has a block of the kind that the test is meant to cover.
+    fn has_block(stream: &[u8], is_kind: fn(&BlockKind) -> bool) -> bool {
+        BlockInspector::new(stream, false).any(|block| is_kind(&block.unwrap().kind))
+    }
 
-                // symbols.push((num_repeated - 3) as u16);
-            }
-            REPEAT_0_CODELEN_3_10_SYMBOL => {
-                let num_repeated = (reader.read_bits(REPEAT_0_CODELEN_3_10_ARG_LEN)? + 3) as usize;
+    fn check_round_trip(data: &[u8], stream: &[u8]) {
+        // The bytes after the stream must be left alone.
+        let mut input = stream.to_vec();
+        input.extend_from_slice(b"trailing");
 
-                lengths[literal_idx..literal_idx + num_repeated].fill(0);
-                literal_idx += num_repeated;
+        for (input_chunk, output_chunk) in [(1, 1), (1, 7), (3, 1), (5, 300), (64, 5), (4096, 4096)]
+        {
+            let (output, num_read) = inflate_in_chunks(&input, input_chunk, output_chunk).unwrap();
 
-                // symbols.push((num_repeated - 3) as u16);
-            }
-            REPEAT_0_CODELEN_11_138_SYMBOL => {
-                let num_repeated =
-                    (reader.read_bits(REPEAT_0_CODELEN_11_138_ARG_LEN)? + 11) as usize;
+            assert!(
+                output == data,
+                "chunks of {input_chunk} and {output_chunk} bytes"
+            );
+            assert_eq!(num_read, stream.len());
+        }
+    }
+
+    #[test]
+    fn inflate_stored_blocks_in_chunks() {
+        let data = sample_data();
+        let options = DeflateOptions {
+            level: 0,
+            ..Default::default()
+        };
+        let stream = compressed(&data, options);
+
+        assert!(has_block(&stream, |kind| matches!(kind, BlockKind::Stored)));
+        check_round_trip(&data, &stream);
+    }
 
-                lengths[literal_idx..literal_idx + num_repeated].fill(0);
-                literal_idx += num_repeated;
+    #[test]
+    fn inflate_fixed_block_in_chunks() {
+        let data = b"abracadabra, abracadabra";
+        let stream = compressed(data, DeflateOptions::default());
 
-                // symbols.push((num_repeated - 11) as u16);
-            }
-            _ => {
-                panic!("Unknown header length symbol: {}", code_length);
+        assert!(has_block(&stream, |kind| matches!(kind, BlockKind::Fixed)));
+        check_round_trip(data, &stream);
+    }
+
+    #[test]
+    fn inflate_dynamic_blocks_in_chunks() {
+        let data = sample_data();
+        let stream = compressed(&data, DeflateOptions::default());
+
+        assert!(has_block(&stream, |kind| matches!(
+            kind,
+            BlockKind::Dynamic(_)
+        )));
+        check_round_trip(&data, &stream);
+    }
+
+    #[test]
+    fn inflate_with_empty_output_after_needing_input() {
+        let stream = compressed(&sample_data(), DeflateOptions::default());
+        let mut inflater = Inflater::new();
+        let mut buffer = vec![0; 1 << 16];
+
+        // The buffer holds bits from the previous calls whenever a step needs more input.
+        for &byte in &stream[..1000] {
+            let status = inflater.inflate(&[byte], &mut buffer);
+            assert_eq!(status.num_read, 1);
+
+            let status = inflater.inflate(&[], &mut []);
+            assert_eq!((status.num_read, status.num_written), (0, 0));
+        }
+    }
+
+    #[test]
+    fn inflate_corrupt_streams_one_byte_at_a_time() {
+        let data = sample_data();
+
+        // Corrupting the block headers makes steps that span several calls fail.
+        for level in [0, 6] {
+            let options = DeflateOptions {
+                level,
+                ..Default::default()
+            };
+            let stream = compressed(&data[..10_000], options);
+
+            for i in 0..stream.len().min(200) {
+                let mut corrupt = stream.clone();
+                corrupt[i] ^= 0xff;
+
+                // Any outcome is fine as long as it doesn't panic.
+                let _ = inflate_in_chunks(&corrupt, 1, 1000);
             }
         }
     }
 
-    // print_header_symbols(&symbols, &length_table);
+    #[test]
+    fn inflate_empty_stream_in_chunks() {
+        let stream = compressed(b"", DeflateOptions::default());
 
-    // TODO: Not supported yet
-    for _ in 0..num_distance_codes {
-        let mut iter = length_huffman_tree.create_walk_iter();
+        check_round_trip(b"", &stream);
+    }
 
-        while !iter.leaf {
-            let bit = reader.read_bits(1)? != 0;
-            iter = length_huffman_tree.walk(iter, bit).unwrap();
+    #[test]
+    fn dictionary_round_trip() {
+        let dictionary = b"the quick brown fox jumps over the lazy dog. ".repeat(4);
+        let data = b"the lazy dog jumps over the quick brown fox.";
+        let options = DeflateOptions {
+            dictionary: Some(dictionary.clone()),
+            ..Default::default()
+        };
+        let stream = compressed(data, options);
+
+        assert!(stream.len() < compressed(data, DeflateOptions::default()).len());
+
+        let mut output = Vec::new();
+        decompress_with_dictionary(&mut Cursor::new(&stream), &mut output, &dictionary).unwrap();
+        assert_eq!(output, data);
+
+        // Without the dictionary, the first match refers back to nothing.
+        let error = decompress(&mut Cursor::new(&stream), &mut Vec::new()).unwrap_err();
+        assert!(
+            matches!(error, DecodeError::DistanceTooFarBack),
+            "{error:?}"
+        );
+
+        // Same through the inflater, one byte at a time.
+        let mut inflater = Inflater::new();
+        inflater.set_dictionary(&dictionary);
+        let mut output = Vec::new();
+        for byte in &stream {
+            let mut buffer = [0; 64];
+            let status = inflater.inflate(std::slice::from_ref(byte), &mut buffer);
+            output.extend_from_slice(&buffer[..status.num_written]);
         }
+        assert_eq!(output, data);
+    }
 
-        // TODO
+    #[test]
+    fn only_the_end_of_a_long_dictionary_is_used() {
+        let mut dictionary = vec![b'x'; 40_000];
+        dictionary.extend_from_slice(b"a sentence at the very end of the dictionary");
+        let data = b"a sentence at the very end";
+        let options = DeflateOptions {
+            dictionary: Some(dictionary.clone()),
+            ..Default::default()
+        };
+        let stream = compressed(data, options);
+        assert!(decompress(&mut Cursor::new(&stream), &mut Vec::new()).is_err());
+
+        let mut output = Vec::new();
+        decompress_with_dictionary(&mut Cursor::new(&stream), &mut output, &dictionary).unwrap();
+        assert_eq!(output, data);
+
+        // The decoder drops the same prefix, so passing only the end works too.
+        let mut output = Vec::new();
+        let end = &dictionary[dictionary.len() - 32768..];
+        decompress_with_dictionary(&mut Cursor::new(&stream), &mut output, end).unwrap();
+        assert_eq!(output, data);
     }
 
-    Ok(HuffmanTable::from_lengths(&lengths[0..num_literals]))
-}
+    // Packs the fields of a crafted stream, each being a value and its number of bits.
+    fn stream(fields: &[(u64, usize)]) -> Vec<u8> {
+        let mut writer = BitWriter::new(Vec::new());
+        for &(value, length) in fields {
+            writer.write_bits(value, length).unwrap();
+        }
 
-#[allow(dead_code)]
-fn print_header_symbols(symbols: &[u16], table: &HuffmanTable) {
-    let mut idx = 0;
+        writer.align_to_byte().unwrap();
+        writer.into_inner()
+    }
 
-    println!("start header");
-    while idx < symbols.len() {
-        let symbol = symbols[idx];
+    // Huffman codes are packed starting from their most significant bit.
+    fn code(code: u64, length: usize) -> (u64, usize) {
+        (code.reverse_bits() >> (64 - length), length)
+    }
 
-        match symbol {
-            0_u16..=15_u16 => {
-                println!(
-                    "{:<16}! {:?}",
-                    format!("lens {}", symbol),
-                    table.code(symbol as usize)
-                );
-            }
-            REPEAT_PREV_3_6_SYMBOL => {
-                idx += 1;
-                println!(
-                    "{:<16}! {:?}",
-                    format!("repeat {}", symbols[idx] + 3),
-                    table.code(symbol as usize)
-                );
-            }
-            REPEAT_0_CODELEN_3_10_SYMBOL => {
-                idx += 1;
-                println!(
-                    "{:<16}! {:?}",
-                    format!("zeros {}", symbols[idx] + 3),
-                    table.code(symbol as usize)
-                );
-            }
-            REPEAT_0_CODELEN_11_138_SYMBOL => {
-                idx += 1;
-                println!(
-                    "{:<16}! {:?}",
-                    format!("zeros {}", symbols[idx] + 11),
-                    table.code(symbol as usize)
-                );
-            }
-            _ => {}
-        }
+    // A dynamic block header with 257 literal codes, 1 distance code, and the given code length
+    // code lengths, for the symbols 16, 17, 18 and 0.
+    fn dynamic_header(code_length_lengths: [u64; 4]) -> Vec<(u64, usize)> {
+        let mut fields = vec![(1, 1), (2, 2), (0, 5), (0, 5), (0, 4)];
+        fields.extend(code_length_lengths.map(|length| (length, 3)));
+        fields
+    }
+
+    // Decodes the stream with `decompress`, which reads it in large chunks, and with an `Inflater`
+    // fed one byte at a time, and checks that both fail the same way.
+    fn decode_error(stream: &[u8]) -> DecodeError {
+        let error = decompress(&mut Cursor::new(stream), &mut Vec::new()).unwrap_err();
+        let chunked_error = inflate_in_chunks(stream, 1, 1).unwrap_err();
+
+        assert_eq!(
+            std::mem::discriminant(&error),
+            std::mem::discriminant(&chunked_error),
+            "{error:?} and {chunked_error:?}"
+        );
+
+        error
+    }
+
+    #[test]
+    fn invalid_block_type() {
+        let error = decode_error(&stream(&[(1, 1), (3, 2)]));
+
+        assert!(matches!(error, DecodeError::InvalidBlockType), "{error:?}");
+    }
+
+    #[test]
+    fn oversubscribed_code() {
+        let error = decode_error(&stream(&dynamic_header([1, 1, 1, 1])));
+
+        assert!(
+            matches!(error, DecodeError::OversubscribedCode),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn incomplete_code() {
+        let error = decode_error(&stream(&dynamic_header([2, 2, 2, 0])));
+
+        assert!(matches!(error, DecodeError::IncompleteCode), "{error:?}");
+    }
+
+    #[test]
+    fn repeat_without_previous_length() {
+        // 0 gets the code 0 and 16 the code 1, which comes first.
+        let mut fields = dynamic_header([1, 0, 0, 1]);
+        fields.extend([code(1, 1), (0, 2)]);
+        let error = decode_error(&stream(&fields));
+
+        assert!(
+            matches!(error, DecodeError::RepeatWithoutPreviousLength),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn distance_too_far_back() {
+        // A fixed block starting with a match of length 258 at distance 24577, whose distance
+        // takes several bytes to read.
+        let fields = [(1, 1), (1, 2), code(0b11000101, 8), code(29, 5), (0, 13)];
+        let error = decode_error(&stream(&fields));
+
+        assert!(
+            matches!(error, DecodeError::DistanceTooFarBack),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn truncated_input() {
+        let data = sample_data();
+        let stream = compressed(&data, DeflateOptions::default());
+        let error = decode_error(&stream[..stream.len() / 2]);
 
-        idx += 1;
+        assert!(matches!(error, DecodeError::TruncatedInput), "{error:?}");
     }
-    println!("end header\n");
 }
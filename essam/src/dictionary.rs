@@ -0,0 +1,233 @@
+// Builds preset dictionaries from samples of the data they will be used for.
+//
+// Reference: Liao, Petri, Moffat, Wirth. "Effective Construction of Relative Lempel-Ziv
+// Dictionaries" (2016), the COVER algorithm used by zstd.
+//
+// Scoring
+// =======
+// A dictionary helps when the data repeats content of it, so it should consist of the substrings
+// that appear in as many samples as possible. Every substring of `dmer_size` bytes (a dmer) is given
+// a frequency: the number of samples it appears in. Appearing many times within a single sample
+// doesn't count more, since the compressor already finds those repetitions without a dictionary.
+// The score of a segment of `segment_size` bytes is the sum of the frequencies of the distinct
+// dmers it contains.
+//
+// Selecting Segments
+// ==================
+// The samples are concatenated and cut into as many epochs as there are segments in the
+// dictionary. In each epoch, the segment with the highest score is found using a sliding window
+// that keeps count of the dmers inside it. The frequencies of its dmers are then set to zero, so
+// that later segments aren't rewarded for the same content.
+//
+// Ordering
+// ========
+// Matches are cheaper the closer they are, and the start of the dictionary is the first thing to
+// slide out of the window as data is compressed. So the segments are written from the lowest score
+// to the highest, which leaves the most useful content right before the data.
+
+use crate::lz77::WINDOW_SIZE;
+use std::collections::HashMap;
+use std::path::Path;
+
+pub struct TrainOptions {
+    /// The maximum size of the dictionary. Only the last 32 KiB can be referenced by deflate.
+    pub max_size: usize,
+    /// The length of the segments the dictionary is made of.
+    pub segment_size: usize,
+    /// The length of the substrings that are counted, at most 8.
+    pub dmer_size: usize,
+}
+
+struct Segment {
+    start: usize,
+    end: usize,
+    score: u64,
+}
+
+impl Default for TrainOptions {
+    fn default() -> Self {
+        Self {
+            max_size: WINDOW_SIZE,
+            segment_size: 256,
+            dmer_size: 6,
+        }
+    }
+}
+
+/// Builds a dictionary from the samples, with the most useful content at the end.
+pub fn train(samples: &[Vec<u8>], options: &TrainOptions) -> Vec<u8> {
+    debug_assert!((1..=8).contains(&options.dmer_size));
+    debug_assert!(options.segment_size >= options.dmer_size);
+
+    let data = samples.concat();
+    let dmers = sample_dmers(samples, options.dmer_size);
+
+    let mut freqs: HashMap<u64, u32> = HashMap::new();
+    for sample_dmers in sample_boundaries(samples).map(|range| &dmers[range]) {
+        let mut distinct: Vec<u64> = sample_dmers.iter().flatten().copied().collect();
+        distinct.sort_unstable();
+        distinct.dedup();
+
+        for dmer in distinct {
+            *freqs.entry(dmer).or_insert(0) += 1;
+        }
+    }
+
+    let num_epochs = (options.max_size / options.segment_size)
+        .min(data.len() / options.segment_size)
+        .max(1);
+    let epoch_size = data.len() / num_epochs;
+
+    let mut segments = Vec::new();
+    for epoch in 0..num_epochs {
+        let start = epoch * epoch_size;
+        let end = if epoch + 1 == num_epochs {
+            data.len()
+        } else {
+            start + epoch_size
+        };
+
+        if let Some(segment) = best_segment(&dmers, &mut freqs, start, end, options) {
+            segments.push(segment);
+        }
+    }
+
+    // Keep the best segments that fit, and put them last.
+    segments.sort_by_key(|segment| std::cmp::Reverse(segment.score));
+    let mut size = 0;
+    segments.retain(|segment| {
+        size += segment.end - segment.start;
+        size <= options.max_size
+    });
+    segments.reverse();
+
+    let mut dictionary = Vec::with_capacity(size.min(options.max_size));
+    for segment in segments {
+        dictionary.extend_from_slice(&data[segment.start..segment.end]);
+    }
+
+    dictionary
+}
+
+/// Reads every file under the directory as a sample, in a stable order.
+pub fn read_samples(dir: impl AsRef<Path>) -> std::io::Result<Vec<Vec<u8>>> {
+    let mut paths = Vec::new();
+    let mut dirs = vec![dir.as_ref().to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                paths.push(entry.path());
+            }
+        }
+    }
+
+    paths.sort();
+    paths.iter().map(std::fs::read).collect()
+}
+
+// Finds the segment with the highest score in data[start..end], and clears the frequencies of its
+// dmers. Returns nothing if no segment has a positive score.
+fn best_segment(
+    dmers: &[Option<u64>],
+    freqs: &mut HashMap<u64, u32>,
+    start: usize,
+    end: usize,
+    options: &TrainOptions,
+) -> Option<Segment> {
+    // A segment of `segment_size` bytes contains this many dmers.
+    let num_dmers = options.segment_size - options.dmer_size + 1;
+
+    let mut active: HashMap<u64, u32> = HashMap::new();
+    let mut score = 0;
+    let mut best = Segment {
+        start,
+        end: start,
+        score: 0,
+    };
+
+    for pos in start..end {
+        if let Some(dmer) = dmers[pos] {
+            let count = active.entry(dmer).or_insert(0);
+            if *count == 0 {
+                score += freqs.get(&dmer).copied().unwrap_or(0) as u64;
+            }
+            *count += 1;
+        }
+
+        // Remove the dmer that just left the segment.
+        if pos >= start + num_dmers {
+            if let Some(dmer) = dmers[pos - num_dmers] {
+                let count = active.get_mut(&dmer).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    score -= freqs.get(&dmer).copied().unwrap_or(0) as u64;
+                }
+            }
+        }
+
+        if score > best.score {
+            let segment_start = (pos + 1).saturating_sub(num_dmers).max(start);
+            best = Segment {
+                start: segment_start,
+                end: pos + options.dmer_size,
+                score,
+            };
+        }
+    }
+
+    if best.score == 0 {
+        return None;
+    }
+
+    // Trim the ends that don't start any useful dmer, and clear the ones that are kept.
+    let is_useful = |pos: &usize| {
+        dmers[*pos]
+            .and_then(|dmer| freqs.get(&dmer))
+            .is_some_and(|&freq| freq > 0)
+    };
+    let last_dmer = best.end - options.dmer_size;
+    let first = (best.start..=last_dmer).find(is_useful)?;
+    let last = (first..=last_dmer).rev().find(is_useful)?;
+
+    for &dmer in dmers[first..=last].iter().flatten() {
+        freqs.insert(dmer, 0);
+    }
+
+    Some(Segment {
+        start: first,
+        end: last + options.dmer_size,
+        score: best.score,
+    })
+}
+
+// Returns the dmer starting at every position of the concatenated samples, or nothing where it
+// would cross into the next sample.
+fn sample_dmers(samples: &[Vec<u8>], dmer_size: usize) -> Vec<Option<u64>> {
+    let mut dmers = Vec::new();
+
+    for sample in samples {
+        for pos in 0..sample.len() {
+            let dmer = sample.get(pos..pos + dmer_size).map(|bytes| {
+                bytes
+                    .iter()
+                    .fold(0, |dmer, &byte| (dmer << 8) | byte as u64)
+            });
+            dmers.push(dmer);
+        }
+    }
+
+    dmers
+}
+
+// Returns the range of positions of each sample in the concatenated samples.
+fn sample_boundaries(samples: &[Vec<u8>]) -> impl Iterator<Item = std::ops::Range<usize>> + '_ {
+    samples.iter().scan(0, |start, sample| {
+        let range = *start..*start + sample.len();
+        *start = range.end;
+        Some(range)
+    })
+}
@@ -0,0 +1,86 @@
+use thiserror::Error;
+
+/// Why a compressed stream couldn't be decoded.
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("not in gzip format")]
+    BadMagic,
+    #[error("unknown compression method {0}")]
+    UnknownCompressionMethod(u8),
+    #[error("reserved header flags set: {0:#04x}")]
+    ReservedFlags(u8),
+    #[error("invalid extra field")]
+    InvalidExtraField,
+    #[error("incorrect header check")]
+    HeaderCheckFailed,
+    #[error("invalid window size: {0} bits")]
+    InvalidWindowSize(u8),
+    #[error("need preset dictionary {0:#010x}")]
+    DictionaryRequired(u32),
+    #[error("wrong preset dictionary: expected {expected:#010x}, got {actual:#010x}")]
+    DictionaryMismatch { expected: u32, actual: u32 },
+    #[error("invalid block type")]
+    InvalidBlockType,
+    #[error("stored block length doesn't match its complement")]
+    StoredLengthMismatch,
+    #[error("too many length or distance symbols")]
+    TooManySymbols,
+    #[error("oversubscribed code")]
+    OversubscribedCode,
+    #[error("incomplete code")]
+    IncompleteCode,
+    #[error("missing end-of-block code")]
+    MissingEndOfBlock,
+    #[error("invalid code")]
+    InvalidCode,
+    #[error("repeat with no previous length")]
+    RepeatWithoutPreviousLength,
+    #[error("too many code lengths")]
+    TooManyCodeLengths,
+    #[error("invalid length symbol")]
+    InvalidLengthSymbol,
+    #[error("invalid distance symbol")]
+    InvalidDistanceSymbol,
+    #[error("distance too far back")]
+    DistanceTooFarBack,
+    #[error("header CRC mismatch: expected {expected:#06x}, got {actual:#06x}")]
+    HeaderCrcMismatch { expected: u16, actual: u16 },
+    #[error("CRC-32 mismatch: expected {expected:#010x}, got {actual:#010x}")]
+    CrcMismatch { expected: u32, actual: u32 },
+    #[error("length mismatch: expected {expected}, got {actual}")]
+    SizeMismatch { expected: u32, actual: u32 },
+    #[error("Adler-32 mismatch: expected {expected:#010x}, got {actual:#010x}")]
+    AdlerMismatch { expected: u32, actual: u32 },
+    #[error("trailing garbage after the last member")]
+    TrailingData,
+    #[error("truncated input")]
+    TruncatedInput,
+    #[error(transparent)]
+    Io(std::io::Error),
+}
+
+// Decoders implementing `Read` have to return their errors as `std::io::Error`, so wrap them in a
+// way that can be undone.
+impl From<DecodeError> for std::io::Error {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::Io(error) => error,
+            DecodeError::TruncatedInput => {
+                std::io::Error::new(std::io::ErrorKind::UnexpectedEof, error)
+            }
+            _ => std::io::Error::new(std::io::ErrorKind::InvalidData, error),
+        }
+    }
+}
+
+impl From<std::io::Error> for DecodeError {
+    fn from(error: std::io::Error) -> Self {
+        match error.downcast::<DecodeError>() {
+            Ok(error) => error,
+            Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+                DecodeError::TruncatedInput
+            }
+            Err(error) => DecodeError::Io(error),
+        }
+    }
+}
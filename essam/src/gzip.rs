@@ -1,138 +1,1204 @@
 use crate::deflate::{
-    compress as deflate_compress, decompress as deflate_decompress, DeflateOptions,
+    CompressionReport, DeflateDecoder, DeflateEncoder, DeflateOptions, Inflater, StatusCode,
+    MAX_LEVEL,
 };
-use crc::{Crc, CRC_32_ISO_HDLC};
+use crate::error::DecodeError;
+use crc::{Crc, Digest, CRC_32_ISO_HDLC};
+use std::ffi::OsStr;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Read, Seek, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn compress(input_path: String, output_path: String) -> std::io::Result<()> {
-    let input_file = File::open(&input_path)?;
-    let output_file = File::create(&output_path)?;
+static CRC_32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
-    let mut buf_reader = BufReader::new(input_file);
-    let mut buf_writer = BufWriter::new(output_file);
+const ID: [u8; 2] = [0x1f, 0x8b];
+const DEFLATE_CM: u8 = 8;
 
-    const ID: u16 = 0x8b1f;
-    const DEFLATE_CM: u8 = 8;
+const FTEXT_MASK: u8 = 0b00000001;
+const FHCRC_MASK: u8 = 0b00000010;
+const FEXTRA_MASK: u8 = 0b00000100;
+const FNAME_MASK: u8 = 0b00001000;
+const FCOMMENT_MASK: u8 = 0b00010000;
+const RESERVED_FLAGS_MASK: u8 = 0b11100000;
 
-    buf_writer.write(&ID.to_le_bytes())?;
-    buf_writer.write(&DEFLATE_CM.to_le_bytes())?;
+// The extra subfield of BGZF (used for genomics data), which holds the size of the member minus one.
+const BGZF_SUBFIELD_ID: [u8; 2] = *b"BC";
 
-    // TODO
-    let flags: u8 = 0b00001000;
-    buf_writer.write(&flags.to_le_bytes())?;
+// XFL values for the slowest and the fastest compression.
+const XFL_MAX_COMPRESSION: u8 = 2;
+const XFL_FASTEST: u8 = 4;
 
-    // TODO
-    let mtime: u32 = 0;
-    buf_writer.write(&mtime.to_le_bytes())?;
+/// The header of a gzip member. The file name and the comment are kept as stored, which should be
+/// Latin-1 without zero bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzHeader {
+    is_text: bool,
+    mtime: u32,
+    xfl: u8,
+    os: Os,
+    extra: Option<Vec<ExtraSubfield>>,
+    filename: Option<Vec<u8>>,
+    comment: Option<Vec<u8>>,
+    has_header_crc: bool,
+}
 
-    // TODO
-    let xfl: u8 = 4;
-    buf_writer.write(&xfl.to_le_bytes())?;
+/// A subfield of the extra field, identified by two bytes (SI1 and SI2).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraSubfield {
+    pub id: [u8; 2],
+    pub data: Vec<u8>,
+}
 
-    // TODO
-    let os: u8 = 255;
-    buf_writer.write(&os.to_le_bytes())?;
+/// The file system on which the compression took place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Os {
+    Fat,
+    Amiga,
+    Vms,
+    Unix,
+    VmCms,
+    AtariTos,
+    Hpfs,
+    Macintosh,
+    ZSystem,
+    CpM,
+    Tops20,
+    Ntfs,
+    Qdos,
+    AcornRiscos,
+    Unknown,
+    /// A value not defined by RFC1952.
+    Other(u8),
+}
 
-    // YUCK FIXME
-    let filename = Path::new(&input_path)
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap();
-    buf_writer.write(filename.as_bytes())?;
-    buf_writer.write(&(0 as u8).to_le_bytes())?; // Write null terminator
+pub struct GzHeaderBuilder {
+    header: GzHeader,
+}
 
-    deflate_compress(&mut buf_reader, &mut buf_writer, DeflateOptions::default())?;
+/// Compresses data written to it into a gzip member.
+pub struct GzEncoder<W: Write> {
+    encoder: DeflateEncoder<W>,
+    digest: Digest<'static, u32>,
+    num_bytes: u64,
+}
 
-    // FIXME: This is inefficient. Maybe calculate the crc while we're compressing using deflate.
-    buf_reader.rewind()?;
+/// Decompresses a gzip stream read from the inner reader. Members are decompressed one after the
+/// other, and their outputs concatenated.
+pub struct GzDecoder<R: Read> {
+    reader: BufReader<CountingReader<R>>,
+    options: DecompressOptions,
+    state: GzDecoderState,
+    inflater: Inflater,
+    // The CRC-32 and size of the output of the current member.
+    digest: Digest<'static, u32>,
+    num_bytes: u64,
+    headers: Vec<GzHeader>,
+    trailing_data: Option<TrailingData>,
+    error_offset: Option<u64>,
+}
 
-    let (crc, size) = compute_crc_and_size(&mut buf_reader);
-    buf_writer.write(&crc.to_le_bytes())?;
-    buf_writer.write(&size.to_le_bytes())?;
+enum GzDecoderState {
+    Header,
+    Body,
+    Done,
+}
 
-    buf_writer.flush()
+// Counts the bytes read through it.
+struct CountingReader<R: Read> {
+    reader: R,
+    num_bytes: u64,
 }
 
-pub fn decompress(input_path: String, output_path: String) -> std::io::Result<()> {
-    const FHCRC_MASK: u8 = 0b00000010;
-    const FEXTRA_MASK: u8 = 0b00000100;
-    const FNAME_MASK: u8 = 0b00001000;
-    const FCOMMENT_MASK: u8 = 0b00010000;
+#[derive(Debug, Clone, Copy)]
+pub struct DecompressOptions {
+    /// Whether to check the CRC-32 and size in the trailer, and the header CRC if present. Turning
+    /// this off allows recovering the data of damaged files.
+    pub verify: bool,
+    /// What to do with data after the last member that isn't another member.
+    pub trailing_data: TrailingDataPolicy,
+}
 
-    let input_file = File::open(&input_path)?;
-    let output_file = File::create(&output_path)?;
+/// What the header and trailer of a member say, and where it is in the input.
+#[derive(Debug, Clone)]
+pub struct MemberInfo {
+    pub header: GzHeader,
+    /// The position of the start of the member.
+    pub offset: u64,
+    /// The size of the member, including its header and trailer.
+    pub compressed_size: u64,
+    /// The CRC-32 of the uncompressed data stored in the trailer.
+    pub crc: u32,
+    /// The size of the uncompressed data modulo 2^32 stored in the trailer.
+    pub isize: u32,
+    /// The actual size of the uncompressed data, if the member was inflated to find its end.
+    pub uncompressed_size: Option<u64>,
+}
 
-    let mut buf_reader = BufReader::new(input_file);
+/// What to do with trailing data, like the zero padding added by some tape drives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingDataPolicy {
+    /// Fail with `DecodeError::TrailingData`.
+    Error,
+    /// Skip it silently.
+    Ignore,
+    /// Skip it, and report it in `Decompressed::trailing_data`.
+    Warn,
+}
+
+/// The kind of data found after the last member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingData {
+    Zeros,
+    Garbage,
+}
+
+/// What was found while decompressing a file.
+#[derive(Debug, Clone)]
+pub struct Decompressed {
+    /// The headers of all the members, in order.
+    pub headers: Vec<GzHeader>,
+    /// The trailing data that was skipped, only reported with `TrailingDataPolicy::Warn`.
+    pub trailing_data: Option<TrailingData>,
+}
+
+impl Default for DecompressOptions {
+    fn default() -> Self {
+        Self {
+            verify: true,
+            trailing_data: TrailingDataPolicy::Warn,
+        }
+    }
+}
+
+/// Compresses a file, storing its name and modification time in the header. Returns the report of
+/// the blocks if `stats` was set in the options.
+pub fn compress(
+    input_path: impl AsRef<Path>,
+    output_path: impl AsRef<Path>,
+    options: DeflateOptions,
+) -> std::io::Result<Option<CompressionReport>> {
+    let input_path = input_path.as_ref();
+    let mut input_file = File::open(input_path)?;
+    let output_file = File::create(output_path)?;
+
+    let mut builder = GzHeader::builder()
+        .xfl_for_options(&options)
+        .os(Os::native());
+    if let Ok(modified) = input_file.metadata()?.modified() {
+        builder = builder.modified(modified);
+    }
+    if let Some(filename) = input_path.file_name() {
+        builder = builder.filename(to_latin1(filename));
+    }
+    let header = builder.build();
+
+    let mut encoder = GzEncoder::new(BufWriter::new(output_file), &header, options)?;
+    std::io::copy(&mut input_file, &mut encoder)?;
+
+    let (mut writer, report) = encoder.finish_with_report()?;
+    writer.flush()?;
+    Ok(report)
+}
+
+/// Decompresses a file, which may consist of several members whose outputs are concatenated.
+pub fn decompress(
+    input_path: impl AsRef<Path>,
+    output_path: impl AsRef<Path>,
+    options: DecompressOptions,
+) -> Result<Decompressed, DecodeError> {
+    let input_file = File::open(input_path)?;
+    let output_file = File::create(output_path)?;
+
+    let mut decoder = GzDecoder::with_options(input_file, options);
     let mut buf_writer = BufWriter::new(output_file);
 
-    // FIXME
-    let mut buffer: [u8; 10] = [0; 10];
+    std::io::copy(&mut decoder, &mut buf_writer)?;
+    buf_writer.flush()?;
 
-    // Read id, flags, modification time, extra flags, and os
-    buf_reader.read_exact(&mut buffer[0..10])?;
+    Ok(Decompressed {
+        headers: decoder.headers,
+        trailing_data: decoder.trailing_data,
+    })
+}
+
+/// Reads the headers and trailers of all the members, stopping at the end of the input or at data
+/// that isn't a member. Finding where a member ends requires inflating it, unless its size is
+/// stored in the header like BGZF does. Nothing is verified.
+pub fn scan_members(reader: &mut (impl BufRead + Seek)) -> Result<Vec<MemberInfo>, DecodeError> {
+    let mut members = Vec::new();
+
+    loop {
+        let offset = reader.stream_position()?;
 
-    assert!(buffer[0] == 0x1f);
-    assert!(buffer[1] == 0x8b);
+        // There's always at least one member, even in an empty file.
+        if !members.is_empty() {
+            let mut magic = Vec::with_capacity(2);
+            reader.take(2).read_to_end(&mut magic)?;
+            if magic != ID {
+                break;
+            }
+
+            reader.seek(SeekFrom::Start(offset))?;
+        }
 
-    let flags = buffer[3];
+        let header = GzHeader::read(reader, false)?;
 
-    // FIXME
-    if flags & FEXTRA_MASK != 0 {
-        buf_reader.read_exact(&mut buffer[0..2])?;
-        let xlen = u16::from_le_bytes([buffer[0], buffer[1]]);
+        let uncompressed_size = match bgzf_member_size(&header) {
+            Some(size) => {
+                let header_end = reader.stream_position()?;
+                let trailer_start = (offset + size)
+                    .checked_sub(8)
+                    .filter(|&start| start >= header_end)
+                    .ok_or(DecodeError::InvalidExtraField)?;
 
-        // Ignore extra field.
-        buf_reader.seek_relative(xlen as i64)?;
+                reader.seek(SeekFrom::Start(trailer_start))?;
+                None
+            }
+            None => {
+                let mut decoder = DeflateDecoder::new(&mut *reader);
+                let size = std::io::copy(&mut decoder, &mut std::io::sink())?;
+
+                let num_remaining = decoder.remaining().len();
+                reader.seek(SeekFrom::Current(-(num_remaining as i64)))?;
+                Some(size)
+            }
+        };
+
+        let mut trailer = [0; 8];
+        reader.read_exact(&mut trailer)?;
+
+        members.push(MemberInfo {
+            header,
+            offset,
+            compressed_size: reader.stream_position()? - offset,
+            crc: u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]),
+            isize: u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]),
+            uncompressed_size,
+        });
     }
 
-    // FIXME
-    if flags & FNAME_MASK != 0 {
-        // Read file name
-        let mut name = Vec::new();
-        buf_reader.read_until(0, &mut name)?;
+    Ok(members)
+}
+
+// Returns the size of the whole member if the header has a BGZF subfield.
+fn bgzf_member_size(header: &GzHeader) -> Option<u64> {
+    let subfield = header
+        .extra()?
+        .iter()
+        .find(|subfield| subfield.id == BGZF_SUBFIELD_ID && subfield.data.len() == 2)?;
+
+    Some(u16::from_le_bytes([subfield.data[0], subfield.data[1]]) as u64 + 1)
+}
+
+/// Converts a file name to Latin-1, as required in the header. Characters that Latin-1 doesn't
+/// have are replaced by `?`. Names that aren't valid Unicode are kept as they are on Unix, since
+/// they're probably in a legacy 8-bit encoding already.
+pub fn to_latin1(name: &OsStr) -> Vec<u8> {
+    #[cfg(unix)]
+    if name.to_str().is_none() {
+        use std::os::unix::ffi::OsStrExt;
+        return name.as_bytes().to_vec();
+    }
+
+    name.to_string_lossy()
+        .chars()
+        .map(|c| u8::try_from(c).unwrap_or(b'?'))
+        .collect()
+}
+
+/// Converts a Latin-1 file name or comment from the header to a string.
+pub fn from_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| byte as char).collect()
+}
+
+impl<W: Write> GzEncoder<W> {
+    /// Creates an encoder, and writes the header right away.
+    pub fn new(mut writer: W, header: &GzHeader, options: DeflateOptions) -> std::io::Result<Self> {
+        // The format has no way to tell the decoder about it.
+        if options.dictionary.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "gzip doesn't support preset dictionaries",
+            ));
+        }
+
+        header.write(&mut writer)?;
+
+        Ok(Self {
+            encoder: DeflateEncoder::new(writer, options),
+            digest: CRC_32.digest(),
+            num_bytes: 0,
+        })
     }
 
-    // FIXME
-    if flags & FCOMMENT_MASK != 0 {
-        // Read comment
-        let mut comment = Vec::new();
-        buf_reader.read_until(0, &mut comment)?;
+    /// Compresses the remaining data, writes the trailer, and returns the writer.
+    pub fn finish(self) -> std::io::Result<W> {
+        let (writer, _) = self.finish_with_report()?;
+        Ok(writer)
     }
 
-    // FIXME
-    if flags & FHCRC_MASK != 0 {
-        // Skip CRC
-        buf_reader.seek_relative(2)?;
+    /// Same as `finish`, but also returns the report of all the blocks if `stats` was set in the
+    /// options.
+    pub fn finish_with_report(self) -> std::io::Result<(W, Option<CompressionReport>)> {
+        let (mut writer, report) = self.encoder.finish_with_report()?;
+
+        // ISIZE is the size modulo 2^32.
+        writer.write_all(&self.digest.finalize().to_le_bytes())?;
+        writer.write_all(&(self.num_bytes as u32).to_le_bytes())?;
+
+        Ok((writer, report))
     }
+}
 
-    deflate_decompress(&mut buf_reader, &mut buf_writer)?;
+impl<W: Write> Write for GzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let num_bytes = self.encoder.write(buf)?;
 
-    buf_reader.read_exact(&mut buffer[0..8])?;
+        self.digest.update(&buf[0..num_bytes]);
+        self.num_bytes += num_bytes as u64;
 
-    // TODO
-    // let crc = u32::from_le_bytes(buffer[0..4].try_into().unwrap());
-    // let size = u32::from_le_bytes(buffer[4..8].try_into().unwrap());
+        Ok(num_bytes)
+    }
 
-    Ok(())
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.encoder.flush()
+    }
 }
 
-fn compute_crc_and_size(reader: &mut impl Read) -> (u32, u32) {
-    let crc_obj = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-    let mut digest = crc_obj.digest();
+impl<R: Read> GzDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_options(reader, DecompressOptions::default())
+    }
+
+    pub fn with_options(reader: R, options: DecompressOptions) -> Self {
+        Self {
+            reader: BufReader::new(CountingReader {
+                reader,
+                num_bytes: 0,
+            }),
+            options,
+            state: GzDecoderState::Header,
+            inflater: Inflater::new(),
+            digest: CRC_32.digest(),
+            num_bytes: 0,
+            headers: Vec::new(),
+            trailing_data: None,
+            error_offset: None,
+        }
+    }
+
+    /// The header of the first member, once it has been read.
+    pub fn header(&self) -> Option<&GzHeader> {
+        self.headers.first()
+    }
+
+    /// The headers of the members read so far.
+    pub fn headers(&self) -> &[GzHeader] {
+        &self.headers
+    }
+
+    /// The data found after the last member, once the end is reached. Only reported with
+    /// `TrailingDataPolicy::Warn`.
+    pub fn trailing_data(&self) -> Option<TrailingData> {
+        self.trailing_data
+    }
 
-    let mut tot_size = 0;
+    /// The number of bytes of the input consumed so far.
+    pub fn position(&self) -> u64 {
+        self.reader.get_ref().num_bytes - self.reader.buffer().len() as u64
+    }
 
-    let mut buffer: [u8; 512] = [0; 512];
-    while let Ok(read_bytes) = reader.read(&mut buffer) {
-        if read_bytes == 0 {
-            break;
+    /// Where the first error was found in the input: the start of the member for a bad header, the
+    /// start of the CRC-32 or ISIZE field for a mismatch, and where decoding stopped otherwise.
+    pub fn error_offset(&self) -> Option<u64> {
+        self.error_offset
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner().reader
+    }
+
+    /// Reads the header of the first member if it wasn't read yet, and returns it. This allows
+    /// looking at the header before reading any data.
+    pub fn read_header(&mut self) -> Result<&GzHeader, DecodeError> {
+        if self.headers.is_empty() {
+            let result = self.start_member();
+            self.check(result)?;
         }
-        tot_size += read_bytes;
-        digest.update(&buffer[0..read_bytes]);
+
+        Ok(&self.headers[0])
     }
 
-    (digest.finalize(), tot_size as u32)
+    // Records where an error happened, unless a more precise offset is already known.
+    fn check<T>(&mut self, result: Result<T, DecodeError>) -> Result<T, DecodeError> {
+        if result.is_err() && self.error_offset.is_none() {
+            self.error_offset = Some(self.position());
+        }
+
+        result
+    }
+
+    fn fail(&mut self, error: DecodeError, offset: u64) -> DecodeError {
+        self.error_offset.get_or_insert(offset);
+        error
+    }
+
+    // Decompresses into `buf`, going through the members until some output is produced or the
+    // input ends.
+    fn read_members(&mut self, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        loop {
+            match self.state {
+                GzDecoderState::Header => self.start_member()?,
+                GzDecoderState::Body => {
+                    let input = self.reader.fill_buf()?;
+                    let is_eof = input.is_empty();
+
+                    let status = self.inflater.inflate(input, buf);
+                    self.reader.consume(status.num_read);
+
+                    let num_written = status.num_written;
+                    self.digest.update(&buf[0..num_written]);
+                    self.num_bytes += num_written as u64;
+
+                    match status.code {
+                        StatusCode::StreamEnd => self.read_trailer()?,
+                        StatusCode::NeedsOutput => {}
+                        StatusCode::Error(error) => return Err(error),
+                        StatusCode::NeedsInput if num_written == 0 && is_eof => {
+                            return Err(DecodeError::TruncatedInput)
+                        }
+                        StatusCode::NeedsInput => {}
+                    }
+
+                    if num_written > 0 || buf.is_empty() {
+                        return Ok(num_written);
+                    }
+                }
+                GzDecoderState::Done => return Ok(0),
+            }
+        }
+    }
+
+    // Reads the header of the next member, or handles the end of the file.
+    fn start_member(&mut self) -> Result<(), DecodeError> {
+        let start = self.position();
+
+        // Peek at the first two bytes to see whether another member follows.
+        let mut magic = Vec::with_capacity(2);
+        (&mut self.reader).take(2).read_to_end(&mut magic)?;
+
+        // There's always at least one member, even in an empty file.
+        if !self.headers.is_empty() {
+            if magic.is_empty() {
+                self.state = GzDecoderState::Done;
+                return Ok(());
+            }
+            if magic != ID {
+                self.state = GzDecoderState::Done;
+                return self
+                    .skip_trailing_data(&magic)
+                    .map_err(|error| self.fail(error, start));
+            }
+        }
+
+        let header = match GzHeader::read(&mut magic.chain(&mut self.reader), self.options.verify) {
+            Ok(header) => header,
+            Err(DecodeError::TruncatedInput) => return Err(DecodeError::TruncatedInput),
+            Err(error) => return Err(self.fail(error, start)),
+        };
+        self.headers.push(header);
+
+        self.state = GzDecoderState::Body;
+        self.inflater = Inflater::new();
+        self.digest = CRC_32.digest();
+        self.num_bytes = 0;
+
+        Ok(())
+    }
+
+    fn read_trailer(&mut self) -> Result<(), DecodeError> {
+        let start = self.position();
+
+        let mut trailer = [0; 8];
+        self.reader.read_exact(&mut trailer)?;
+
+        let expected_crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let expected_size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+
+        let crc = self.digest.clone().finalize();
+        // ISIZE is the size modulo 2^32.
+        let size = self.num_bytes as u32;
+
+        if self.options.verify && expected_crc != crc {
+            let error = DecodeError::CrcMismatch {
+                expected: expected_crc,
+                actual: crc,
+            };
+            return Err(self.fail(error, start));
+        }
+        if self.options.verify && expected_size != size {
+            let error = DecodeError::SizeMismatch {
+                expected: expected_size,
+                actual: size,
+            };
+            return Err(self.fail(error, start + 4));
+        }
+
+        self.state = GzDecoderState::Header;
+        Ok(())
+    }
+
+    // Applies the trailing data policy, given the first bytes of the trailing data.
+    fn skip_trailing_data(&mut self, start: &[u8]) -> Result<(), DecodeError> {
+        match self.options.trailing_data {
+            TrailingDataPolicy::Error => Err(DecodeError::TrailingData),
+            TrailingDataPolicy::Ignore => Ok(()),
+            TrailingDataPolicy::Warn => {
+                let mut is_zeros = start.iter().all(|&byte| byte == 0);
+                while is_zeros {
+                    let buf = self.reader.fill_buf()?;
+                    if buf.is_empty() {
+                        break;
+                    }
+
+                    is_zeros = buf.iter().all(|&byte| byte == 0);
+                    let len = buf.len();
+                    self.reader.consume(len);
+                }
+
+                self.trailing_data = Some(if is_zeros {
+                    TrailingData::Zeros
+                } else {
+                    TrailingData::Garbage
+                });
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let result = self.read_members(buf);
+        Ok(self.check(result)?)
+    }
+}
+
+impl GzHeader {
+    pub fn builder() -> GzHeaderBuilder {
+        GzHeaderBuilder::new()
+    }
+
+    /// Whether the data is probably text (FTEXT).
+    pub fn is_text(&self) -> bool {
+        self.is_text
+    }
+
+    /// The modification time of the original file in seconds since the Unix epoch, or zero if
+    /// unknown.
+    pub fn mtime(&self) -> u32 {
+        self.mtime
+    }
+
+    /// The extra flags, which tell how much the data was compressed.
+    pub fn xfl(&self) -> u8 {
+        self.xfl
+    }
+
+    pub fn os(&self) -> Os {
+        self.os
+    }
+
+    /// The subfields of the extra field, if there's one.
+    pub fn extra(&self) -> Option<&[ExtraSubfield]> {
+        self.extra.as_deref()
+    }
+
+    /// The name of the original file, without the directory.
+    pub fn filename(&self) -> Option<&[u8]> {
+        self.filename.as_deref()
+    }
+
+    pub fn comment(&self) -> Option<&[u8]> {
+        self.comment.as_deref()
+    }
+
+    /// Whether the header is protected by a CRC (FHCRC).
+    pub fn has_header_crc(&self) -> bool {
+        self.has_header_crc
+    }
+
+    /// Reads and parses a header. The header CRC, if present, is only checked if `verify` is set.
+    pub fn read(reader: &mut impl BufRead, verify: bool) -> Result<Self, DecodeError> {
+        // All the bytes of the header, which are needed for checking the header CRC.
+        let mut bytes = vec![0; 10];
+
+        // Read id, flags, modification time, extra flags, and os
+        reader.read_exact(&mut bytes[0..10])?;
+
+        if bytes[0..2] != ID {
+            return Err(DecodeError::BadMagic);
+        }
+        if bytes[2] != DEFLATE_CM {
+            return Err(DecodeError::UnknownCompressionMethod(bytes[2]));
+        }
+
+        let flags = bytes[3];
+        if flags & RESERVED_FLAGS_MASK != 0 {
+            return Err(DecodeError::ReservedFlags(flags));
+        }
+
+        let mut header = GzHeader {
+            is_text: flags & FTEXT_MASK != 0,
+            mtime: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            xfl: bytes[8],
+            os: Os::from(bytes[9]),
+            extra: None,
+            filename: None,
+            comment: None,
+            has_header_crc: flags & FHCRC_MASK != 0,
+        };
+
+        if flags & FEXTRA_MASK != 0 {
+            let mut xlen = [0; 2];
+            reader.read_exact(&mut xlen)?;
+            bytes.extend_from_slice(&xlen);
+
+            let start = bytes.len();
+            bytes.resize(start + u16::from_le_bytes(xlen) as usize, 0);
+            reader.read_exact(&mut bytes[start..])?;
+
+            header.extra = Some(parse_extra_subfields(&bytes[start..])?);
+        }
+
+        if flags & FNAME_MASK != 0 {
+            header.filename = Some(read_zero_terminated(reader, &mut bytes)?);
+        }
+
+        if flags & FCOMMENT_MASK != 0 {
+            header.comment = Some(read_zero_terminated(reader, &mut bytes)?);
+        }
+
+        if header.has_header_crc {
+            let mut crc = [0; 2];
+            reader.read_exact(&mut crc)?;
+
+            // The header CRC is the lower half of the CRC-32 of the header before it.
+            let expected = u16::from_le_bytes(crc);
+            let actual = CRC_32.checksum(&bytes) as u16;
+
+            if verify && expected != actual {
+                return Err(DecodeError::HeaderCrcMismatch { expected, actual });
+            }
+        }
+
+        Ok(header)
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let invalid_input =
+            |message| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+
+        let mut flags = 0;
+        if self.is_text {
+            flags |= FTEXT_MASK;
+        }
+        if self.has_header_crc {
+            flags |= FHCRC_MASK;
+        }
+        if self.extra.is_some() {
+            flags |= FEXTRA_MASK;
+        }
+        if self.filename.is_some() {
+            flags |= FNAME_MASK;
+        }
+        if self.comment.is_some() {
+            flags |= FCOMMENT_MASK;
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&ID);
+        bytes.push(DEFLATE_CM);
+        bytes.push(flags);
+        bytes.extend_from_slice(&self.mtime.to_le_bytes());
+        bytes.push(self.xfl);
+        bytes.push(self.os.into());
+
+        if let Some(extra) = &self.extra {
+            let mut extra_bytes = Vec::new();
+            for subfield in extra {
+                let len = u16::try_from(subfield.data.len())
+                    .map_err(|_| invalid_input("extra subfield too long"))?;
+
+                extra_bytes.extend_from_slice(&subfield.id);
+                extra_bytes.extend_from_slice(&len.to_le_bytes());
+                extra_bytes.extend_from_slice(&subfield.data);
+            }
+
+            let xlen = u16::try_from(extra_bytes.len())
+                .map_err(|_| invalid_input("extra field too long"))?;
+            bytes.extend_from_slice(&xlen.to_le_bytes());
+            bytes.extend_from_slice(&extra_bytes);
+        }
+
+        for field in [&self.filename, &self.comment].into_iter().flatten() {
+            if field.contains(&0) {
+                return Err(invalid_input("file name or comment contains a zero byte"));
+            }
+
+            bytes.extend_from_slice(field);
+            bytes.push(0);
+        }
+
+        if self.has_header_crc {
+            let crc = CRC_32.checksum(&bytes) as u16;
+            bytes.extend_from_slice(&crc.to_le_bytes());
+        }
+
+        writer.write_all(&bytes)
+    }
+}
+
+impl Default for GzHeader {
+    fn default() -> Self {
+        Self {
+            is_text: false,
+            mtime: 0,
+            xfl: 0,
+            os: Os::Unknown,
+            extra: None,
+            filename: None,
+            comment: None,
+            has_header_crc: false,
+        }
+    }
+}
+
+impl GzHeaderBuilder {
+    pub fn new() -> Self {
+        Self {
+            header: GzHeader::default(),
+        }
+    }
+
+    pub fn text(mut self, is_text: bool) -> Self {
+        self.header.is_text = is_text;
+        self
+    }
+
+    pub fn mtime(mut self, mtime: u32) -> Self {
+        self.header.mtime = mtime;
+        self
+    }
+
+    /// Sets the modification time, which is left unknown (zero) if it doesn't fit.
+    pub fn modified(mut self, time: SystemTime) -> Self {
+        self.header.mtime = time
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|duration| u32::try_from(duration.as_secs()).ok())
+            .unwrap_or(0);
+        self
+    }
+
+    pub fn xfl(mut self, xfl: u8) -> Self {
+        self.header.xfl = xfl;
+        self
+    }
+
+    /// Sets the extra flags to tell whether the data was compressed with the fastest or the
+    /// slowest settings.
+    pub fn xfl_for_options(mut self, options: &DeflateOptions) -> Self {
+        self.header.xfl = if options.ultra || options.level >= MAX_LEVEL {
+            XFL_MAX_COMPRESSION
+        } else if options.level == 1 {
+            XFL_FASTEST
+        } else {
+            0
+        };
+        self
+    }
+
+    pub fn os(mut self, os: Os) -> Self {
+        self.header.os = os;
+        self
+    }
+
+    /// Adds a subfield to the extra field.
+    pub fn extra_subfield(mut self, id: [u8; 2], data: impl Into<Vec<u8>>) -> Self {
+        let subfield = ExtraSubfield {
+            id,
+            data: data.into(),
+        };
+
+        self.header
+            .extra
+            .get_or_insert_with(Vec::new)
+            .push(subfield);
+        self
+    }
+
+    pub fn filename(mut self, filename: impl Into<Vec<u8>>) -> Self {
+        self.header.filename = Some(filename.into());
+        self
+    }
+
+    pub fn comment(mut self, comment: impl Into<Vec<u8>>) -> Self {
+        self.header.comment = Some(comment.into());
+        self
+    }
+
+    pub fn header_crc(mut self, has_header_crc: bool) -> Self {
+        self.header.has_header_crc = has_header_crc;
+        self
+    }
+
+    pub fn build(self) -> GzHeader {
+        self.header
+    }
+}
+
+impl Default for GzHeaderBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Os {
+    /// The file system of the current platform, as far as the format can tell.
+    pub fn native() -> Self {
+        if cfg!(unix) {
+            Os::Unix
+        } else if cfg!(windows) {
+            Os::Ntfs
+        } else {
+            Os::Unknown
+        }
+    }
+}
+
+impl From<u8> for Os {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Os::Fat,
+            1 => Os::Amiga,
+            2 => Os::Vms,
+            3 => Os::Unix,
+            4 => Os::VmCms,
+            5 => Os::AtariTos,
+            6 => Os::Hpfs,
+            7 => Os::Macintosh,
+            8 => Os::ZSystem,
+            9 => Os::CpM,
+            10 => Os::Tops20,
+            11 => Os::Ntfs,
+            12 => Os::Qdos,
+            13 => Os::AcornRiscos,
+            255 => Os::Unknown,
+            _ => Os::Other(value),
+        }
+    }
+}
+
+impl From<Os> for u8 {
+    fn from(os: Os) -> Self {
+        match os {
+            Os::Fat => 0,
+            Os::Amiga => 1,
+            Os::Vms => 2,
+            Os::Unix => 3,
+            Os::VmCms => 4,
+            Os::AtariTos => 5,
+            Os::Hpfs => 6,
+            Os::Macintosh => 7,
+            Os::ZSystem => 8,
+            Os::CpM => 9,
+            Os::Tops20 => 10,
+            Os::Ntfs => 11,
+            Os::Qdos => 12,
+            Os::AcornRiscos => 13,
+            Os::Unknown => 255,
+            Os::Other(value) => value,
+        }
+    }
+}
+
+impl std::fmt::Display for Os {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Os::Fat => "FAT",
+            Os::Amiga => "Amiga",
+            Os::Vms => "VMS",
+            Os::Unix => "Unix",
+            Os::VmCms => "VM/CMS",
+            Os::AtariTos => "Atari TOS",
+            Os::Hpfs => "HPFS",
+            Os::Macintosh => "Macintosh",
+            Os::ZSystem => "Z-System",
+            Os::CpM => "CP/M",
+            Os::Tops20 => "TOPS-20",
+            Os::Ntfs => "NTFS",
+            Os::Qdos => "QDOS",
+            Os::AcornRiscos => "Acorn RISCOS",
+            Os::Unknown => "unknown",
+            Os::Other(value) => return write!(f, "unknown ({})", value),
+        };
+
+        f.write_str(name)
+    }
+}
+
+fn parse_extra_subfields(mut bytes: &[u8]) -> Result<Vec<ExtraSubfield>, DecodeError> {
+    let mut subfields = Vec::new();
+
+    while !bytes.is_empty() {
+        if bytes.len() < 4 {
+            return Err(DecodeError::InvalidExtraField);
+        }
+
+        let len = u16::from_le_bytes([bytes[2], bytes[3]]) as usize;
+        let Some(data) = bytes.get(4..4 + len) else {
+            return Err(DecodeError::InvalidExtraField);
+        };
+
+        subfields.push(ExtraSubfield {
+            id: [bytes[0], bytes[1]],
+            data: data.to_vec(),
+        });
+        bytes = &bytes[4 + len..];
+    }
+
+    Ok(subfields)
+}
+
+// Reads a zero terminated field of the header, and returns it without the terminator. The field is
+// also appended to `bytes` along with the terminator.
+fn read_zero_terminated(
+    reader: &mut impl BufRead,
+    bytes: &mut Vec<u8>,
+) -> Result<Vec<u8>, DecodeError> {
+    let start = bytes.len();
+    reader.read_until(0, bytes)?;
+
+    if bytes.last() != Some(&0) {
+        return Err(DecodeError::TruncatedInput);
+    }
+
+    Ok(bytes[start..bytes.len() - 1].to_vec())
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let num_bytes = self.reader.read(buf)?;
+        self.num_bytes += num_bytes as u64;
+        Ok(num_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &[u8] = b"hello, hello, hello world";
+
+    fn member(header: &GzHeader, data: &[u8]) -> Vec<u8> {
+        let mut member = Vec::new();
+        let mut encoder = GzEncoder::new(&mut member, header, DeflateOptions::default()).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap();
+
+        member
+    }
+
+    // Decompresses the input, and returns the output or the error along with its offset.
+    fn decode(
+        input: &[u8],
+        options: DecompressOptions,
+    ) -> (Result<Vec<u8>, DecodeError>, Option<u64>) {
+        let mut decoder = GzDecoder::with_options(input, options);
+        let mut output = Vec::new();
+        let result = decoder.read_to_end(&mut output).map(|_| output);
+
+        (result.map_err(DecodeError::from), decoder.error_offset())
+    }
+
+    // Decompresses the corrupt input without verifying, which must give back the data.
+    fn check_no_verify(input: &[u8]) {
+        let options = DecompressOptions {
+            verify: false,
+            ..Default::default()
+        };
+        let (result, error_offset) = decode(input, options);
+
+        assert_eq!(result.unwrap(), DATA);
+        assert_eq!(error_offset, None);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut member = member(&GzHeader::default(), DATA);
+        member[1] = 0x8c;
+
+        let mut decoder = GzDecoder::new(&member[..]);
+        let error = decoder.read_header().unwrap_err();
+
+        assert!(matches!(error, DecodeError::BadMagic), "{error:?}");
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut member = member(&GzHeader::default(), DATA);
+        let crc_offset = member.len() - 8;
+        member[crc_offset] ^= 1;
+
+        let (result, error_offset) = decode(&member, DecompressOptions::default());
+        let error = result.unwrap_err();
+
+        assert!(
+            matches!(error, DecodeError::CrcMismatch { .. }),
+            "{error:?}"
+        );
+        assert_eq!(error_offset, Some(crc_offset as u64));
+        check_no_verify(&member);
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let mut member = member(&GzHeader::default(), DATA);
+        let size_offset = member.len() - 4;
+        member[size_offset] ^= 1;
+
+        let (result, error_offset) = decode(&member, DecompressOptions::default());
+        let error = result.unwrap_err();
+
+        assert!(
+            matches!(error, DecodeError::SizeMismatch { .. }),
+            "{error:?}"
+        );
+        assert_eq!(error_offset, Some(size_offset as u64));
+        check_no_verify(&member);
+    }
+
+    #[test]
+    fn header_crc_mismatch() {
+        let header = GzHeader::builder()
+            .filename("hello.txt")
+            .header_crc(true)
+            .build();
+        let mut member = member(&header, DATA);
+        // The header CRC comes right after the file name and its terminator.
+        member[10 + "hello.txt".len() + 1] ^= 1;
+
+        let (result, error_offset) = decode(&member, DecompressOptions::default());
+        let error = result.unwrap_err();
+
+        assert!(
+            matches!(error, DecodeError::HeaderCrcMismatch { .. }),
+            "{error:?}"
+        );
+        assert_eq!(error_offset, Some(0));
+        check_no_verify(&member);
+    }
+
+    // Decompresses two members followed by the given data, and returns the output or the error,
+    // along with the trailing data reported.
+    fn decode_with_trailing_data(
+        trailing_data: &[u8],
+        policy: TrailingDataPolicy,
+    ) -> (Result<Vec<u8>, DecodeError>, Option<TrailingData>) {
+        let mut input = member(&GzHeader::default(), DATA);
+        input.extend(member(&GzHeader::default(), DATA));
+        input.extend_from_slice(trailing_data);
+
+        let options = DecompressOptions {
+            trailing_data: policy,
+            ..Default::default()
+        };
+        let mut decoder = GzDecoder::with_options(input.as_slice(), options);
+        let mut output = Vec::new();
+        let result = decoder.read_to_end(&mut output).map(|_| output);
+
+        (result.map_err(DecodeError::from), decoder.trailing_data())
+    }
+
+    #[test]
+    fn concatenated_members() {
+        let first = GzHeader::builder().filename("first").build();
+        let second = GzHeader::builder().filename("second").build();
+
+        let mut input = member(&first, b"hello, ");
+        input.extend(member(&second, b"world"));
+        input.extend(member(&GzHeader::default(), b""));
+
+        let mut decoder = GzDecoder::new(input.as_slice());
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+
+        assert_eq!(output, b"hello, world");
+        assert_eq!(decoder.headers(), [first, second, GzHeader::default()]);
+        assert_eq!(decoder.trailing_data(), None);
+    }
+
+    #[test]
+    fn trailing_zeros_are_reported() {
+        let (result, trailing_data) =
+            decode_with_trailing_data(&[0; 1000], TrailingDataPolicy::Warn);
+
+        assert_eq!(result.unwrap(), [DATA, DATA].concat());
+        assert_eq!(trailing_data, Some(TrailingData::Zeros));
+    }
+
+    #[test]
+    fn trailing_garbage_is_reported() {
+        let mut garbage = vec![0; 1000];
+        garbage.extend_from_slice(b"garbage");
+        let (result, trailing_data) = decode_with_trailing_data(&garbage, TrailingDataPolicy::Warn);
+
+        assert_eq!(result.unwrap(), [DATA, DATA].concat());
+        assert_eq!(trailing_data, Some(TrailingData::Garbage));
+    }
+
+    #[test]
+    fn trailing_data_is_an_error() {
+        let mut input = member(&GzHeader::default(), DATA);
+        let member_len = input.len();
+        input.extend_from_slice(b"garbage");
+
+        let options = DecompressOptions {
+            trailing_data: TrailingDataPolicy::Error,
+            ..Default::default()
+        };
+        let (result, error_offset) = decode(&input, options);
+        let error = result.unwrap_err();
+
+        assert!(matches!(error, DecodeError::TrailingData), "{error:?}");
+        assert_eq!(error_offset, Some(member_len as u64));
+
+        // Members alone are fine.
+        let (result, _) = decode_with_trailing_data(b"", TrailingDataPolicy::Error);
+        assert_eq!(result.unwrap(), [DATA, DATA].concat());
+    }
+
+    #[test]
+    fn trailing_data_is_ignored() {
+        let (result, trailing_data) =
+            decode_with_trailing_data(b"garbage", TrailingDataPolicy::Ignore);
+
+        assert_eq!(result.unwrap(), [DATA, DATA].concat());
+        assert_eq!(trailing_data, None);
+    }
+
+    #[test]
+    fn dictionary_is_rejected() {
+        let options = DeflateOptions {
+            dictionary: Some(b"hello".to_vec()),
+            ..Default::default()
+        };
+
+        let result = GzEncoder::new(Vec::new(), &GzHeader::default(), options);
+
+        let error = result.err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
 }
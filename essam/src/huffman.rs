@@ -26,6 +26,22 @@ pub struct WalkIterator {
     pub leaf: bool,
 }
 
+/// A lookup table for decoding a prefix code whose bits come least significant first. Codes of up
+/// to `primary_bits` bits are decoded with a single lookup of the next `primary_bits` bits. Longer
+/// codes point to a subtable for their first `primary_bits` bits, which is indexed by the bits
+/// after them.
+pub struct DecodeTable {
+    entries: Vec<DecodeEntry>,
+    primary_bits: usize,
+}
+
+#[derive(Clone, Copy)]
+enum DecodeEntry {
+    Invalid,
+    Symbol { symbol: u16, length: u8 },
+    Subtable { offset: u16, bits: u8 },
+}
+
 #[derive(Copy, Clone)]
 struct Node {
     left: Option<NonMaxU16>,
@@ -50,7 +66,7 @@ impl PrefixCode {
 impl std::fmt::Debug for PrefixCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for i in 0..self.length {
-            write!(f, "{}", (self.code & ((1 as u32) << i)) >> i)?;
+            write!(f, "{}", (self.code & (1_u32 << i)) >> i)?;
         }
         Ok(())
     }
@@ -60,11 +76,9 @@ impl HuffmanTree {
     pub fn build(freqs: &[u32]) -> HuffmanTree {
         let num_symbols = freqs.len();
         let capacity = 2 * num_symbols - 1;
-        assert!(capacity <= (std::u16::MAX - 1).into());
+        assert!(capacity <= (u16::MAX - 1).into());
 
-        let mut nodes = Vec::<Node>::new();
-
-        nodes.reserve(capacity);
+        let mut nodes = Vec::<Node>::with_capacity(capacity);
 
         // Reverse so that it becomes a min heap.
         let mut heap = BinaryHeap::<std::cmp::Reverse<HeapEntry>>::new();
@@ -108,7 +122,7 @@ impl HuffmanTree {
     }
 
     fn is_leaf_node(&self, idx: usize) -> bool {
-        (idx as usize) < self.num_symbols
+        idx < self.num_symbols
     }
 
     pub fn create_walk_iter(&self) -> WalkIterator {
@@ -149,7 +163,7 @@ impl HuffmanTable {
         freqs: &[u32],
         max_length: usize,
     ) -> Result<Self, PackageMergeError> {
-        let lengths = package_merge(&freqs, max_length)?;
+        let lengths = package_merge(freqs, max_length)?;
 
         let table = Self::from_lengths(&lengths);
 
@@ -164,8 +178,7 @@ impl HuffmanTable {
 
     pub fn from_lengths(lengths: &[u8]) -> Self {
         let mut lengths_count: [u32; 32] = [0; 32];
-        let mut codes = Vec::new();
-        codes.reserve(lengths.len());
+        let mut codes = Vec::with_capacity(lengths.len());
 
         for length in lengths {
             lengths_count[*length as usize] += 1;
@@ -254,14 +267,15 @@ impl From<&HuffmanTree> for HuffmanTable {
             &mut table,
         );
 
-        return table;
+        table
     }
 }
 
 impl From<&HuffmanTable> for HuffmanTree {
     fn from(table: &HuffmanTable) -> Self {
         let num_symbols = table.codes.len();
-        let capacity = 2 * num_symbols - 1;
+        // With a single symbol the root would be the leaf itself, so make room for a separate root.
+        let capacity = (2 * num_symbols - 1).max(num_symbols + 1);
 
         let mut nodes = Vec::<Node>::new();
         nodes.resize(
@@ -283,7 +297,7 @@ impl From<&HuffmanTable> for HuffmanTree {
                 continue;
             }
             for bit_idx in 0..code.length {
-                let bit = code.code & ((0b1 as u32) << bit_idx);
+                let bit = code.code & (0b1_u32 << bit_idx);
                 if bit == 0 {
                     match nodes[crawler_idx].left {
                         None => {
@@ -330,3 +344,91 @@ impl From<&HuffmanTable> for HuffmanTree {
         HuffmanTree { nodes, num_symbols }
     }
 }
+
+impl DecodeTable {
+    pub fn new(table: &HuffmanTable, primary_bits: usize) -> Self {
+        let max_length = table
+            .codes
+            .iter()
+            .map(|code| code.length as usize)
+            .max()
+            .unwrap_or(0);
+        let primary_bits = primary_bits.min(max_length).max(1);
+        let primary_mask = (1 << primary_bits) - 1;
+
+        let mut entries = vec![DecodeEntry::Invalid; 1 << primary_bits];
+
+        // Each subtable needs enough bits for the longest code starting with its prefix.
+        let mut subtable_bits = vec![0; 1 << primary_bits];
+        for code in &table.codes {
+            let length = code.length as usize;
+
+            if length > primary_bits {
+                let prefix = code.code as usize & primary_mask;
+                subtable_bits[prefix] = subtable_bits[prefix].max(length - primary_bits);
+            }
+        }
+
+        for (prefix, &bits) in subtable_bits.iter().enumerate() {
+            if bits > 0 {
+                entries[prefix] = DecodeEntry::Subtable {
+                    offset: entries.len() as u16,
+                    bits: bits as u8,
+                };
+                entries.resize(entries.len() + (1 << bits), DecodeEntry::Invalid);
+            }
+        }
+
+        for (symbol, code) in table.codes.iter().enumerate() {
+            let length = code.length as usize;
+            let entry = DecodeEntry::Symbol {
+                symbol: symbol as u16,
+                length: code.length,
+            };
+
+            if length == 0 {
+                continue;
+            }
+
+            // The bits after the code can be anything, so the entry is repeated for all of them.
+            if length <= primary_bits {
+                for idx in (code.code as usize..1 << primary_bits).step_by(1 << length) {
+                    entries[idx] = entry;
+                }
+            } else if let DecodeEntry::Subtable { offset, bits } =
+                entries[code.code as usize & primary_mask]
+            {
+                let subtable_code = code.code as usize >> primary_bits;
+
+                for idx in (subtable_code..1 << bits).step_by(1 << (length - primary_bits)) {
+                    entries[offset as usize + idx] = entry;
+                }
+            }
+        }
+
+        Self {
+            entries,
+            primary_bits,
+        }
+    }
+
+    /// Decodes the code at the start of `bits`, and returns its symbol and length. Returns `None`
+    /// if no code matches.
+    pub fn lookup(&self, bits: u64) -> Option<(usize, u8)> {
+        let mut entry = self.entries[bits as usize & ((1 << self.primary_bits) - 1)];
+
+        if let DecodeEntry::Subtable {
+            offset,
+            bits: subtable_bits,
+        } = entry
+        {
+            let idx = (bits >> self.primary_bits) as usize & ((1 << subtable_bits) - 1);
+            entry = self.entries[offset as usize + idx];
+        }
+
+        match entry {
+            DecodeEntry::Symbol { symbol, length } => Some((symbol as usize, length)),
+            _ => None,
+        }
+    }
+}
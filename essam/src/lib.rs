@@ -1,8 +1,14 @@
 pub mod bitio;
 pub mod bitset;
+pub mod block_split;
 pub mod deflate;
+pub mod dictionary;
+pub mod error;
 pub mod gzip;
 pub mod huffman;
+pub mod lz77;
+pub mod optimal_parse;
 pub mod package_merge;
 pub mod reverse_bits;
+pub mod zlib;
 pub mod nonmax;
@@ -0,0 +1,258 @@
+// Implements LZ77 match finding over a sliding window using hash chains.
+//
+// Reference: RFC1951 section 4, and the zlib implementation it describes.
+//
+// Hash Chains
+// ===========
+// Every position in the window is hashed using the 3 bytes starting at it (the minimum match
+// length). The table `head` maps each hash to the most recent position having that hash, and
+// `prev` maps each position to the previous position having the same hash. Walking `prev` starting
+// from `head` visits all candidate match positions, from the closest to the furthest.
+//
+// Sliding
+// =======
+// The data is kept in a single buffer holding the history followed by the bytes that have not been
+// processed yet. Once the current position goes past two windows, the first window is dropped and
+// all stored positions are moved back by a window size. Since `prev` is indexed by the position
+// modulo the window size, moving by exactly a window keeps every entry in place.
+
+use crate::nonmax::NonMaxU32;
+
+pub const WINDOW_SIZE: usize = 32768;
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MatchFinderConfig {
+    /// The maximum distance of a match, at most `WINDOW_SIZE`.
+    pub window_size: usize,
+    /// The maximum number of candidates to check for each position.
+    pub max_chain: usize,
+    /// When trying to beat a match at least this long, only a quarter of the chain is checked.
+    pub good_length: usize,
+    /// The search stops as soon as a match at least this long is found.
+    pub nice_length: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub length: u16,
+    pub distance: u16,
+}
+
+pub struct MatchFinder {
+    data: Vec<u8>,
+    head: Vec<Option<NonMaxU32>>,
+    prev: Vec<Option<NonMaxU32>>,
+    // Current position in `data`.
+    pos: usize,
+    // All positions before this one are inserted into the hash chains.
+    hashed: usize,
+    config: MatchFinderConfig,
+}
+
+impl MatchFinder {
+    pub fn new(config: MatchFinderConfig) -> Self {
+        debug_assert!(config.window_size <= WINDOW_SIZE);
+
+        Self {
+            data: Vec::with_capacity(3 * WINDOW_SIZE),
+            head: vec![None; HASH_SIZE],
+            prev: vec![None; WINDOW_SIZE],
+            pos: 0,
+            hashed: 0,
+            config,
+        }
+    }
+
+    /// Primes the history with a preset dictionary, so that the data can be matched against it. Only
+    /// the last `WINDOW_SIZE` bytes are used. Must be called before anything is pushed.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        debug_assert!(self.data.is_empty());
+
+        let dictionary = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+        self.data.extend_from_slice(dictionary);
+        self.pos = dictionary.len();
+    }
+
+    /// Appends data to be processed after all the data pushed so far.
+    pub fn push(&mut self, bytes: &[u8]) {
+        while self.pos >= 2 * WINDOW_SIZE {
+            self.slide();
+        }
+
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// The number of pushed bytes that have not been processed yet.
+    pub fn lookahead(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    /// The bytes that have not been processed yet.
+    pub fn pending(&self) -> &[u8] {
+        &self.data[self.pos..]
+    }
+
+    /// Finds the longest match for the bytes at the current position that is at most `max_length`
+    /// bytes long. The search stops early once a match of the nice length is found.
+    pub fn find_match(&mut self, max_length: usize) -> Option<Match> {
+        self.find_match_impl(max_length, MIN_MATCH - 1, self.config.max_chain, None)
+    }
+
+    /// Same as `find_match`, but also appends to `matches` every match found during the search
+    /// that was longer than all the ones before it. These are in increasing order of length and
+    /// distance.
+    pub fn find_all_matches(&mut self, max_length: usize, matches: &mut Vec<Match>) {
+        self.find_match_impl(
+            max_length,
+            MIN_MATCH - 1,
+            self.config.max_chain,
+            Some(matches),
+        );
+    }
+
+    /// Same as `find_match`, but only returns a match that is longer than `length`. The search is
+    /// shortened if `length` is already good.
+    pub fn find_longer_match(&mut self, max_length: usize, length: usize) -> Option<Match> {
+        let max_chain = if length >= self.config.good_length {
+            self.config.max_chain / 4
+        } else {
+            self.config.max_chain
+        };
+
+        self.find_match_impl(max_length, length.max(MIN_MATCH - 1), max_chain, None)
+    }
+
+    fn find_match_impl(
+        &mut self,
+        max_length: usize,
+        length_to_beat: usize,
+        max_chain: usize,
+        mut all_matches: Option<&mut Vec<Match>>,
+    ) -> Option<Match> {
+        let max_length = max_length.min(self.lookahead());
+        if max_length <= length_to_beat {
+            return None;
+        }
+
+        self.update_hashes(self.pos);
+
+        let data = &self.data[..self.pos + max_length];
+        let current = &data[self.pos..];
+        let nice_length = self.config.nice_length.min(max_length);
+
+        let mut best = Match {
+            length: length_to_beat as u16,
+            distance: 0,
+        };
+
+        let mut candidate = self.head[Self::hash(current)];
+        let mut chain_length = 0;
+
+        while let Some(candidate_pos) = candidate {
+            let candidate_pos = candidate_pos.get() as usize;
+            let distance = self.pos - candidate_pos;
+
+            if distance > self.config.window_size || chain_length >= max_chain {
+                break;
+            }
+
+            // Check the byte that would extend the best match first, as most candidates fail there.
+            let best_length = best.length as usize;
+            if data[candidate_pos + best_length] == current[best_length] {
+                let length = current
+                    .iter()
+                    .zip(&data[candidate_pos..])
+                    .take_while(|(lhs, rhs)| lhs == rhs)
+                    .count();
+
+                if length > best_length {
+                    best = Match {
+                        length: length as u16,
+                        distance: distance as u16,
+                    };
+
+                    if let Some(all_matches) = all_matches.as_mut() {
+                        all_matches.push(best);
+                    }
+
+                    if length >= nice_length {
+                        break;
+                    }
+                }
+            }
+
+            candidate = self.prev[candidate_pos % WINDOW_SIZE];
+            chain_length += 1;
+        }
+
+        if best.distance == 0 {
+            None
+        } else {
+            Some(best)
+        }
+    }
+
+    /// Moves the current position forward, making the skipped bytes part of the history.
+    pub fn advance(&mut self, num_bytes: usize) {
+        debug_assert!(num_bytes <= self.lookahead());
+
+        self.pos += num_bytes;
+    }
+
+    /// Same as `advance`, but the skipped bytes (other than the current one) are never inserted
+    /// into the hash chains, so they can't be the start of future matches. This is faster for long
+    /// matches.
+    pub fn skip(&mut self, num_bytes: usize) {
+        debug_assert!(num_bytes <= self.lookahead());
+
+        self.update_hashes(self.pos + 1);
+        self.pos += num_bytes;
+        self.hashed = self.hashed.max(self.pos);
+    }
+
+    fn update_hashes(&mut self, to: usize) {
+        // The last two bytes can't be hashed until more data arrives.
+        let to = to.min((self.data.len() + 1).saturating_sub(MIN_MATCH));
+
+        while self.hashed < to {
+            let pos = self.hashed;
+            let hash = Self::hash(&self.data[pos..]);
+
+            self.prev[pos % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = NonMaxU32::new(pos as u32);
+            self.hashed += 1;
+        }
+    }
+
+    fn slide(&mut self) {
+        self.update_hashes(self.pos);
+
+        self.data.drain(0..WINDOW_SIZE);
+        self.pos -= WINDOW_SIZE;
+        self.hashed -= WINDOW_SIZE;
+
+        let slide_position = |position: &mut Option<NonMaxU32>| {
+            *position = position.and_then(|pos| {
+                let pos = pos.get() as usize;
+                if pos < WINDOW_SIZE {
+                    None
+                } else {
+                    NonMaxU32::new((pos - WINDOW_SIZE) as u32)
+                }
+            })
+        };
+
+        self.head.iter_mut().for_each(slide_position);
+        self.prev.iter_mut().for_each(slide_position);
+    }
+
+    fn hash(bytes: &[u8]) -> usize {
+        let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+        (value.wrapping_mul(0x9e3779b1) >> (32 - HASH_BITS)) as usize
+    }
+}
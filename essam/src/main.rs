@@ -1,37 +1,1169 @@
 use clap::Parser;
-use essam::gzip::{compress as gzip_compress, decompress as gzip_decompress};
+use essam::deflate::{
+    BlockInfo, BlockInspector, BlockKind, BlockType, CompressionReport, DeflateOptions,
+    DynamicHeader, Symbol,
+};
+use essam::dictionary::{read_samples, train as train_dictionary, TrainOptions};
+use essam::error::DecodeError;
+use essam::gzip::{
+    compress as gzip_compress, decompress as gzip_decompress, from_latin1, scan_members, to_latin1,
+    DecompressOptions, GzDecoder, GzEncoder, GzHeader, MemberInfo, Os, TrailingData,
+    TrailingDataPolicy,
+};
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, Metadata};
+use std::io::{BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone, clap::Args)]
 struct OperationArgs {
-    input_path: String,
-    output_path: String,
+    input_path: PathBuf,
+    output_path: PathBuf,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+#[group(multiple = false)]
+struct LevelArgs {
+    /// Compress faster
+    #[arg(short = '1', long = "fast")]
+    level_1: bool,
+    #[arg(short = '2', hide = true)]
+    level_2: bool,
+    #[arg(short = '3', hide = true)]
+    level_3: bool,
+    #[arg(short = '4', hide = true)]
+    level_4: bool,
+    #[arg(short = '5', hide = true)]
+    level_5: bool,
+    #[arg(short = '6', hide = true)]
+    level_6: bool,
+    #[arg(short = '7', hide = true)]
+    level_7: bool,
+    #[arg(short = '8', hide = true)]
+    level_8: bool,
+    /// Compress better
+    #[arg(short = '9', long = "best")]
+    level_9: bool,
+}
+
+// Options of every mode that compresses.
+#[derive(Debug, Clone, clap::Args)]
+struct CompressOptionsArgs {
+    #[command(flatten)]
+    level: LevelArgs,
+    /// Use optimal parsing for maximum compression (slow)
+    #[arg(long)]
+    ultra: bool,
+    /// Print statistics about every compressed block
+    #[arg(long)]
+    stats: bool,
+}
+
+// Options of every mode that decompresses.
+#[derive(Debug, Clone, clap::Args)]
+struct DecompressOptionsArgs {
+    /// Don't check the CRCs and the size, to recover the data of damaged files
+    #[arg(long)]
+    no_verify: bool,
+    /// What to do with data after the last member
+    #[arg(long, value_enum, default_value_t = TrailingArg::Warn)]
+    trailing: TrailingArg,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct CompressArgs {
+    #[command(flatten)]
+    operation: OperationArgs,
+    #[command(flatten)]
+    compression: CompressOptionsArgs,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct DecompressArgs {
+    #[command(flatten)]
+    operation: OperationArgs,
+    #[command(flatten)]
+    decompression: DecompressOptionsArgs,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum TrailingArg {
+    Error,
+    Ignore,
+    Warn,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct TrainDictArgs {
+    /// Directory of sample files, searched recursively
+    sample_dir: PathBuf,
+    output_path: PathBuf,
+    /// Maximum size of the dictionary in bytes
+    #[arg(long, default_value_t = 32768, value_parser = clap::value_parser!(u16).range(1..=32768))]
+    max_size: u16,
+    /// Length of the segments the dictionary is made of
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u16).range(8..))]
+    segment_size: u16,
+}
+
+// Options of the default mode, which works like the gzip command.
+#[derive(Debug, Clone, clap::Args)]
+struct GzipArgs {
+    /// Write on standard output, keep original files unchanged
+    #[arg(short = 'c', long = "stdout", visible_alias = "to-stdout")]
+    to_stdout: bool,
+    /// Decompress
+    #[arg(short, long, visible_alias = "uncompress")]
+    decompress: bool,
+    /// Force overwrite of output file and compress links
+    #[arg(short, long)]
+    force: bool,
+    /// Keep (don't delete) input files
+    #[arg(short, long)]
+    keep: bool,
+    /// Don't save or restore the original name and timestamp
+    #[arg(short = 'n', long, overrides_with = "name")]
+    no_name: bool,
+    /// Save or restore the original name and timestamp
+    #[arg(short = 'N', long, overrides_with = "no_name")]
+    name: bool,
+    /// Suppress all warnings
+    #[arg(short, long)]
+    quiet: bool,
+    /// Use suffix SUF on compressed files
+    #[arg(short = 'S', long, value_name = "SUF", default_value = ".gz")]
+    suffix: String,
+    #[command(flatten)]
+    compression: CompressOptionsArgs,
+    #[command(flatten)]
+    decompression: DecompressOptionsArgs,
+    /// Files to (de)compress, or `-` for standard input, which is also used if there are none
+    files: Vec<PathBuf>,
+}
+
+// The outcome of processing a file. Like gzip, a warning still lets the other files be processed,
+// but changes the exit code to 2, and an error to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Status {
+    Success,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct TestArgs {
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    #[command(flatten)]
+    decompression: DecompressOptionsArgs,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct ListArgs {
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct InspectArgs {
+    file: PathBuf,
+    /// Also show every literal and match with the number of bits it took
+    #[arg(long)]
+    symbols: bool,
+    /// The file is a raw deflate stream instead of a gzip file
+    #[arg(long)]
+    raw: bool,
 }
 
 #[derive(Debug, Clone, clap::Subcommand)]
 enum Operation {
-    Compress(OperationArgs),
-    Decompress(OperationArgs),
+    Compress(CompressArgs),
+    Decompress(DecompressArgs),
+    /// Build a preset dictionary from sample files
+    TrainDict(TrainDictArgs),
+    /// Check the integrity of gzip files without writing anything
+    Test(TestArgs),
+    /// List the sizes and stored metadata of gzip files
+    List(ListArgs),
+    /// Show how every deflate block of a file is encoded
+    Inspect(InspectArgs),
 }
 
+// The tools are reached through `--tool` rather than as subcommands, so that no file operand can
+// be mistaken for one: `essam list` compresses a file named `list`, like gzip would.
 #[derive(Debug, clap::Parser)]
 struct Args {
+    #[command(flatten)]
+    gzip: GzipArgs,
+    /// Run another tool instead, with the arguments that follow (see `--tool help`)
+    #[arg(
+        long,
+        value_name = "TOOL",
+        num_args = 1..,
+        allow_hyphen_values = true,
+        exclusive = true
+    )]
+    tool: Option<Vec<OsString>>,
+}
+
+#[derive(Debug, clap::Parser)]
+#[command(bin_name = "essam --tool")]
+struct ToolArgs {
     #[command(subcommand)]
     op: Operation,
 }
 
-fn compress(input_path: String, output_path: String) -> anyhow::Result<()> {
-    gzip_compress(input_path, output_path).map_err(anyhow::Error::from)
+impl LevelArgs {
+    fn level(&self) -> Option<u8> {
+        let flags = [
+            self.level_1,
+            self.level_2,
+            self.level_3,
+            self.level_4,
+            self.level_5,
+            self.level_6,
+            self.level_7,
+            self.level_8,
+            self.level_9,
+        ];
+
+        flags
+            .iter()
+            .position(|&flag| flag)
+            .map(|idx| (idx + 1) as u8)
+    }
+}
+
+impl CompressOptionsArgs {
+    fn deflate_options(&self) -> DeflateOptions {
+        let mut options = DeflateOptions::default();
+        if let Some(level) = self.level.level() {
+            options.level = level;
+        }
+        options.ultra = self.ultra;
+        options.stats = self.stats;
+        options
+    }
+}
+
+impl DecompressOptionsArgs {
+    fn decompress_options(&self) -> DecompressOptions {
+        DecompressOptions {
+            verify: !self.no_verify,
+            trailing_data: self.trailing.into(),
+        }
+    }
+}
+
+impl From<TrailingArg> for TrailingDataPolicy {
+    fn from(arg: TrailingArg) -> Self {
+        match arg {
+            TrailingArg::Error => TrailingDataPolicy::Error,
+            TrailingArg::Ignore => TrailingDataPolicy::Ignore,
+            TrailingArg::Warn => TrailingDataPolicy::Warn,
+        }
+    }
+}
+
+impl GzipArgs {
+    fn run(&self) -> Status {
+        if self.suffix.is_empty() {
+            eprintln!("essam: invalid suffix ''");
+            return Status::Error;
+        }
+
+        let stdin = [PathBuf::from("-")];
+        let files = if self.files.is_empty() {
+            &stdin[..]
+        } else {
+            &self.files[..]
+        };
+
+        let mut status = Status::Success;
+        for path in files {
+            let is_stdin = path.as_os_str() == "-";
+
+            let result = if is_stdin {
+                self.process_stdin()
+            } else if self.decompress {
+                self.decompress_file(path)
+            } else {
+                self.compress_file(path)
+            };
+
+            let file_status = result.unwrap_or_else(|error| {
+                let name = if is_stdin {
+                    "stdin".into()
+                } else {
+                    path.display().to_string()
+                };
+                eprintln!("essam: {name}: {error:#}");
+                Status::Error
+            });
+            status = status.max(file_status);
+        }
+
+        status
+    }
+
+    fn process_stdin(&self) -> anyhow::Result<Status> {
+        if self.decompress {
+            if std::io::stdin().is_terminal() && !self.force {
+                anyhow::bail!(
+                    "compressed data not read from a terminal. Use -f to force decompression."
+                );
+            }
+
+            let mut decoder = GzDecoder::with_options(
+                std::io::stdin().lock(),
+                self.decompression.decompress_options(),
+            );
+            decompress_stream(&mut decoder, std::io::stdout().lock())?;
+
+            Ok(self.trailing_data_status("stdin", &decoder))
+        } else {
+            self.check_stdout()?;
+
+            let options = self.compression.deflate_options();
+            let header = GzHeader::builder()
+                .xfl_for_options(&options)
+                .os(Os::native())
+                .build();
+            compress_stream(
+                std::io::stdin().lock(),
+                std::io::stdout().lock(),
+                &header,
+                options,
+                "stdin",
+            )?;
+
+            Ok(Status::Success)
+        }
+    }
+
+    fn compress_file(&self, path: &Path) -> anyhow::Result<Status> {
+        let Some(metadata) = self.input_metadata(path)? else {
+            return Ok(Status::Warning);
+        };
+
+        if !self.to_stdout
+            && path
+                .as_os_str()
+                .as_encoded_bytes()
+                .ends_with(self.suffix.as_bytes())
+        {
+            return Ok(self.warn(format_args!(
+                "{}: already has {} suffix -- unchanged",
+                path.display(),
+                self.suffix
+            )));
+        }
+
+        let options = self.compression.deflate_options();
+        let mut header = GzHeader::builder()
+            .xfl_for_options(&options)
+            .os(Os::native());
+        if !self.no_name {
+            if let Some(name) = path.file_name() {
+                header = header.filename(to_latin1(name));
+            }
+            if let Ok(modified) = metadata.modified() {
+                header = header.modified(modified);
+            }
+        }
+        let header = header.build();
+
+        let input = File::open(path)?;
+
+        if self.to_stdout {
+            self.check_stdout()?;
+            compress_stream(
+                input,
+                std::io::stdout().lock(),
+                &header,
+                options,
+                &path.display().to_string(),
+            )?;
+            return Ok(Status::Success);
+        }
+
+        let mut output_path = OsString::from(path);
+        output_path.push(&self.suffix);
+        let output_path = PathBuf::from(output_path);
+
+        let Some(output) = self.create_output(&output_path)? else {
+            return Ok(Status::Warning);
+        };
+
+        let name = path.display().to_string();
+        if let Err(error) = compress_stream(input, &output, &header, options, &name) {
+            let _ = std::fs::remove_file(&output_path);
+            return Err(error.into());
+        }
+
+        self.finish_output(path, &metadata, &output, metadata.modified().ok())?;
+        Ok(Status::Success)
+    }
+
+    fn decompress_file(&self, path: &Path) -> anyhow::Result<Status> {
+        // Like gzip, `essam -d file` also finds `file.gz`.
+        let mut path = path.to_path_buf();
+        if !path.exists() {
+            let mut with_suffix = OsString::from(&path);
+            with_suffix.push(&self.suffix);
+            if Path::new(&with_suffix).exists() {
+                path = with_suffix.into();
+            }
+        }
+
+        let Some(metadata) = self.input_metadata(&path)? else {
+            return Ok(Status::Warning);
+        };
+
+        let stripped_path = self.strip_suffix(&path);
+        if stripped_path.is_none() && !self.to_stdout {
+            return Ok(self.warn(format_args!(
+                "{}: unknown suffix -- ignored",
+                path.display()
+            )));
+        }
+
+        let mut decoder =
+            GzDecoder::with_options(File::open(&path)?, self.decompression.decompress_options());
+
+        if self.to_stdout {
+            decompress_stream(&mut decoder, std::io::stdout().lock())?;
+            return Ok(self.trailing_data_status(path.display(), &decoder));
+        }
+
+        let header = decoder.read_header()?.clone();
+
+        // Only the last component of the stored name is used, so that it can't point elsewhere.
+        let stored_name = header
+            .filename()
+            .map(from_latin1)
+            .and_then(|name| name.rsplit('/').next().map(str::to_owned))
+            .filter(|name| !name.is_empty() && name != "." && name != "..");
+
+        let output_path = match stored_name {
+            Some(name) if self.name => path.with_file_name(name),
+            _ => stripped_path.unwrap(),
+        };
+
+        let Some(output) = self.create_output(&output_path)? else {
+            return Ok(Status::Warning);
+        };
+
+        if let Err(error) = decompress_stream(&mut decoder, &output) {
+            let _ = std::fs::remove_file(&output_path);
+            return Err(error);
+        }
+
+        let mtime = if self.name && header.mtime() != 0 {
+            Some(UNIX_EPOCH + Duration::from_secs(header.mtime().into()))
+        } else {
+            metadata.modified().ok()
+        };
+
+        self.finish_output(&path, &metadata, &output, mtime)?;
+        Ok(self.trailing_data_status(path.display(), &decoder))
+    }
+
+    // Returns the metadata of an input file, or nothing after warning if it's not a regular file.
+    fn input_metadata(&self, path: &Path) -> anyhow::Result<Option<Metadata>> {
+        let metadata = std::fs::symlink_metadata(path)?;
+
+        if metadata.is_dir() {
+            return Ok(self.warn_ignored(path, "is a directory -- ignored"));
+        }
+        if metadata.is_symlink() && !self.force {
+            return Ok(self.warn_ignored(path, "is a symbolic link -- ignored"));
+        }
+
+        let metadata = std::fs::metadata(path)?;
+        if !metadata.is_file() {
+            return Ok(self.warn_ignored(path, "is not a regular file -- ignored"));
+        }
+
+        Ok(Some(metadata))
+    }
+
+    // Creates an output file, or returns nothing after warning if it exists and can't be
+    // overwritten.
+    fn create_output(&self, path: &Path) -> anyhow::Result<Option<File>> {
+        if !self.force && std::fs::symlink_metadata(path).is_ok() {
+            return Ok(self.warn_ignored(path, "already exists; not overwritten"));
+        }
+
+        Ok(Some(File::create(path)?))
+    }
+
+    // Gives the output the permissions and timestamp of the input, and deletes the input unless it
+    // should be kept.
+    fn finish_output(
+        &self,
+        input_path: &Path,
+        input_metadata: &Metadata,
+        output: &File,
+        mtime: Option<SystemTime>,
+    ) -> anyhow::Result<()> {
+        output.set_permissions(input_metadata.permissions())?;
+        if let Some(mtime) = mtime {
+            output.set_modified(mtime)?;
+        }
+
+        if !self.keep {
+            std::fs::remove_file(input_path)?;
+        }
+
+        Ok(())
+    }
+
+    // Returns the path without the suffix, if it has one of the suffixes gzip knows. `.tgz` and
+    // `.taz` become `.tar`.
+    fn strip_suffix(&self, path: &Path) -> Option<PathBuf> {
+        let name = path.file_name()?;
+        let suffixes = [
+            (self.suffix.as_str(), ""),
+            (".gz", ""),
+            ("-gz", ""),
+            (".z", ""),
+            ("-z", ""),
+            ("_z", ""),
+            (".tgz", ".tar"),
+            (".taz", ".tar"),
+        ];
+
+        suffixes.iter().find_map(|&(suffix, replacement)| {
+            let mut stem = strip_name_suffix(name, suffix).filter(|stem| !stem.is_empty())?;
+            stem.push(replacement);
+            Some(path.with_file_name(stem))
+        })
+    }
+
+    fn check_stdout(&self) -> anyhow::Result<()> {
+        if std::io::stdout().is_terminal() && !self.force {
+            anyhow::bail!(
+                "compressed data not written to a terminal. Use -f to force compression."
+            );
+        }
+
+        Ok(())
+    }
+
+    fn trailing_data_status<R: Read>(
+        &self,
+        name: impl std::fmt::Display,
+        decoder: &GzDecoder<R>,
+    ) -> Status {
+        trailing_data_status(name, decoder.trailing_data(), self.quiet)
+    }
+
+    fn warn_ignored<T>(&self, path: &Path, message: &str) -> Option<T> {
+        self.warn(format_args!("{}: {message}", path.display()));
+        None
+    }
+
+    fn warn(&self, message: std::fmt::Arguments) -> Status {
+        if !self.quiet {
+            eprintln!("essam: {message}");
+        }
+
+        Status::Warning
+    }
+}
+
+impl Status {
+    fn exit_code(self) -> ExitCode {
+        match self {
+            Status::Success => ExitCode::SUCCESS,
+            Status::Warning => ExitCode::from(2),
+            Status::Error => ExitCode::FAILURE,
+        }
+    }
+}
+
+// Removes a suffix from a file name.
+fn strip_name_suffix(name: &OsStr, suffix: &str) -> Option<OsString> {
+    let stem = name.as_encoded_bytes().strip_suffix(suffix.as_bytes())?;
+
+    // SAFETY: Removing a UTF-8 string from the end splits the name at a valid boundary.
+    Some(unsafe { OsString::from_encoded_bytes_unchecked(stem.to_vec()) })
+}
+
+// Compresses a stream into a single member, and prints the statistics of its blocks if they were
+// asked for in the options.
+fn compress_stream(
+    mut input: impl Read,
+    output: impl Write,
+    header: &GzHeader,
+    options: DeflateOptions,
+    name: &str,
+) -> std::io::Result<()> {
+    let mut encoder = GzEncoder::new(BufWriter::new(output), header, options)?;
+    std::io::copy(&mut input, &mut encoder)?;
+
+    let (mut writer, report) = encoder.finish_with_report()?;
+    writer.flush()?;
+
+    if let Some(report) = report {
+        print_report(name, &report);
+    }
+
+    Ok(())
+}
+
+fn decompress_stream<R: Read>(
+    decoder: &mut GzDecoder<R>,
+    output: impl Write,
+) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(output);
+    std::io::copy(decoder, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn compress(args: CompressArgs) -> anyhow::Result<()> {
+    let options = args.compression.deflate_options();
+    let input_path = args.operation.input_path;
+    let report = gzip_compress(&input_path, args.operation.output_path, options)?;
+    if let Some(report) = report {
+        print_report(&input_path.display().to_string(), &report);
+    }
+
+    Ok(())
 }
 
-fn decompress(input_path: String, output_path: String) -> anyhow::Result<()> {
-    gzip_decompress(input_path, output_path).map_err(anyhow::Error::from)
+// Prints the statistics of every block on standard error, as standard output may have the data.
+// Entropies and bits per symbol are shown as `entropy/bits`, for literal/length and distance
+// symbols separately.
+fn print_report(name: &str, report: &CompressionReport) {
+    eprintln!("essam: {name}: {} blocks", report.blocks.len());
+    eprintln!(
+        "{:>5} {:<7} {:>10} {:>11} {:>8} {:>9} {:>8} {:>7} {:>8} {:>11} {:>11}",
+        "block",
+        "type",
+        "input",
+        "output bits",
+        "header",
+        "literals",
+        "matches",
+        "avg len",
+        "avg dist",
+        "lit/len",
+        "dist"
+    );
+
+    for (idx, block) in report.blocks.iter().enumerate() {
+        let block_type = match block.block_type {
+            BlockType::Stored => "stored",
+            BlockType::Fixed => "fixed",
+            BlockType::Dynamic => "dynamic",
+        };
+
+        eprintln!(
+            "{idx:>5} {block_type:<7} {:>10} {:>11} {:>8} {:>9} {:>8} {:>7.1} {:>8.1} {:>11} {:>11}",
+            block.input_bytes,
+            block.output_bits,
+            block.header_bits,
+            block.num_literals,
+            block.num_matches,
+            block.average_match_length(),
+            block.average_match_distance(),
+            format!(
+                "{:.2}/{:.2}",
+                block.literal_entropy, block.literal_bits_per_symbol
+            ),
+            format!(
+                "{:.2}/{:.2}",
+                block.distance_entropy, block.distance_bits_per_symbol
+            ),
+        );
+    }
+
+    let input_bytes: usize = report.blocks.iter().map(|block| block.input_bytes).sum();
+    let output_bits: usize = report.blocks.iter().map(|block| block.output_bits).sum();
+    let header_bits: usize = report.blocks.iter().map(|block| block.header_bits).sum();
+    let num_literals: usize = report.blocks.iter().map(|block| block.num_literals).sum();
+    let num_matches: usize = report.blocks.iter().map(|block| block.num_matches).sum();
+    let match_length: usize = report
+        .blocks
+        .iter()
+        .map(|block| block.total_match_length)
+        .sum();
+    let match_distance: usize = report
+        .blocks
+        .iter()
+        .map(|block| block.total_match_distance)
+        .sum();
+
+    eprintln!(
+        "{:>5} {:<7} {input_bytes:>10} {output_bits:>11} {header_bits:>8} {num_literals:>9} {num_matches:>8} {:>7.1} {:>8.1} {:.3} bits per byte",
+        "total",
+        "",
+        match_length as f64 / num_matches.max(1) as f64,
+        match_distance as f64 / num_matches.max(1) as f64,
+        output_bits as f64 / input_bytes.max(1) as f64,
+    );
+}
+
+fn decompress(args: DecompressArgs) -> anyhow::Result<()> {
+    let options = args.decompression.decompress_options();
+
+    let input_path = args.operation.input_path;
+    let decompressed = gzip_decompress(&input_path, args.operation.output_path, options)?;
+    trailing_data_status(input_path.display(), decompressed.trailing_data, false);
+
+    Ok(())
+}
+
+// Warns about data after the last member that was ignored, like gzip.
+fn trailing_data_status(
+    name: impl std::fmt::Display,
+    trailing_data: Option<TrailingData>,
+    quiet: bool,
+) -> Status {
+    let message = match trailing_data {
+        Some(TrailingData::Zeros) => "trailing zero bytes ignored",
+        Some(TrailingData::Garbage) => "trailing garbage ignored",
+        None => return Status::Success,
+    };
+
+    if !quiet {
+        eprintln!("essam: {name}: decompression OK, {message}");
+    }
+
+    Status::Warning
 }
 
-fn main() -> anyhow::Result<()> {
+fn train_dict(args: TrainDictArgs) -> anyhow::Result<()> {
+    let samples = read_samples(&args.sample_dir)?;
+    if samples.iter().all(|sample| sample.is_empty()) {
+        anyhow::bail!("no samples found in {}", args.sample_dir.display());
+    }
+
+    let options = TrainOptions {
+        max_size: args.max_size as usize,
+        segment_size: args.segment_size as usize,
+        ..TrainOptions::default()
+    };
+    let dictionary = train_dictionary(&samples, &options);
+
+    std::fs::write(&args.output_path, &dictionary)?;
+    eprintln!(
+        "essam: built a {} byte dictionary from {} samples",
+        dictionary.len(),
+        samples.len()
+    );
+
+    Ok(())
+}
+
+fn test(args: TestArgs) -> std::io::Result<Status> {
+    let mut out = std::io::stdout().lock();
+    let mut status = Status::Success;
+
+    let options = args.decompression.decompress_options();
+    for path in &args.files {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) => {
+                writeln!(out, "{}: FAILED: {error}", path.display())?;
+                status = Status::Error;
+                continue;
+            }
+        };
+
+        let mut decoder = GzDecoder::with_options(file, options);
+        let file_status = match std::io::copy(&mut decoder, &mut std::io::sink()) {
+            Ok(_) => {
+                writeln!(out, "{}: OK", path.display())?;
+                trailing_data_status(path.display(), decoder.trailing_data(), false)
+            }
+            Err(error) => {
+                let offset = decoder.error_offset().unwrap_or(decoder.position());
+                writeln!(
+                    out,
+                    "{}: FAILED at byte {offset}: {}",
+                    path.display(),
+                    DecodeError::from(error)
+                )?;
+                Status::Error
+            }
+        };
+
+        status = status.max(file_status);
+    }
+
+    Ok(status)
+}
+
+fn list(args: ListArgs) -> std::io::Result<Status> {
+    let mut out = std::io::stdout().lock();
+    writeln!(
+        out,
+        "{:>12} {:>12} {:>6} {:>7} {:<12} {:<19} name",
+        "compressed", "uncompressed", "ratio", "members", "os", "mtime (UTC)"
+    )?;
+
+    let mut status = Status::Success;
+    let mut num_listed = 0;
+    let mut total_compressed = 0;
+    let mut total_uncompressed = 0;
+
+    for path in &args.files {
+        match scan_file(path) {
+            Ok((compressed_size, members)) => {
+                let uncompressed_size = list_file(&mut out, compressed_size, &members)?;
+                num_listed += 1;
+                total_compressed += compressed_size;
+                total_uncompressed += uncompressed_size;
+            }
+            Err(error) => {
+                eprintln!("essam: {}: {error}", path.display());
+                status = Status::Error;
+            }
+        }
+    }
+
+    if num_listed > 1 {
+        writeln!(
+            out,
+            "{total_compressed:>12} {total_uncompressed:>12} {:>6} {:>7} {:<12} {:<19} (totals)",
+            format_ratio(total_compressed, total_uncompressed),
+            "",
+            "",
+            ""
+        )?;
+    }
+
+    Ok(status)
+}
+
+// Returns the size of a file and its members.
+fn scan_file(path: &Path) -> Result<(u64, Vec<MemberInfo>), DecodeError> {
+    let file = File::open(path)?;
+    let compressed_size = file.metadata()?.len();
+    let members = scan_members(&mut BufReader::new(file))?;
+
+    Ok((compressed_size, members))
+}
+
+// Prints the line of a file, and returns its uncompressed size.
+fn list_file(
+    out: &mut impl Write,
+    compressed_size: u64,
+    members: &[MemberInfo],
+) -> std::io::Result<u64> {
+    // ISIZE only has the size modulo 4 GiB, so use the actual size where it's known.
+    let isize_total: u64 = members.iter().map(|member| member.isize as u64).sum();
+    let uncompressed_size: u64 = members
+        .iter()
+        .map(|member| member.uncompressed_size.unwrap_or(member.isize as u64))
+        .sum();
+
+    let header = &members[0].header;
+    let name = header.filename().map_or("-".into(), from_latin1);
+
+    writeln!(
+        out,
+        "{compressed_size:>12} {isize_total:>12} {:>6} {:>7} {:<12} {:<19} {name}",
+        format_ratio(compressed_size, uncompressed_size),
+        members.len(),
+        header.os().to_string(),
+        format_mtime(header.mtime()),
+    )?;
+
+    if uncompressed_size != isize_total {
+        writeln!(
+            out,
+            "{:>12} note: ISIZE wrapped past 4 GiB, the uncompressed size is {uncompressed_size}",
+            ""
+        )?;
+    }
+
+    Ok(uncompressed_size)
+}
+
+// Formats the space saved like gzip, which is negative if the data grew.
+fn format_ratio(compressed_size: u64, uncompressed_size: u64) -> String {
+    if uncompressed_size == 0 {
+        return "0.0%".into();
+    }
+
+    let saved = 1.0 - compressed_size as f64 / uncompressed_size as f64;
+    format!("{:.1}%", saved * 100.0)
+}
+
+// Formats seconds since the Unix epoch as a UTC date and time, or `-` if unknown.
+fn format_mtime(mtime: u32) -> String {
+    if mtime == 0 {
+        return "-".into();
+    }
+
+    let days = mtime / 86400;
+    let seconds = mtime % 86400;
+
+    // Converts days to a civil date, from Howard Hinnant's `civil_from_days`. The epoch is moved
+    // to March 1st of year 0, so that leap days come last in each year.
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as u32;
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn inspect(args: InspectArgs) -> anyhow::Result<()> {
+    let data = std::fs::read(&args.file)?;
+    let mut out = std::io::stdout().lock();
+
+    if args.raw {
+        inspect_stream(&mut out, &data, 0, args.symbols)?;
+        return Ok(());
+    }
+
+    let mut input = &data[..];
+    let mut num_members = 0;
+    loop {
+        let offset = data.len() - input.len();
+
+        // Like gzip, anything after the first member that isn't another member is trailing data.
+        let header = match GzHeader::read(&mut input, false) {
+            Err(DecodeError::BadMagic | DecodeError::TruncatedInput) if num_members > 0 => {
+                writeln!(
+                    out,
+                    "{} bytes of trailing data at byte {offset}",
+                    data.len() - offset
+                )?;
+                break;
+            }
+            result => result?,
+        };
+
+        let stream_offset = data.len() - input.len();
+        writeln!(out,
+            "member {num_members} at byte {offset}, name {}, deflate stream at byte {stream_offset}",
+            header.filename().map_or("-".into(), from_latin1),
+        )?;
+
+        let stream_len = inspect_stream(&mut out, input, stream_offset, args.symbols)?;
+        input = input
+            .get(stream_len + 8..)
+            .ok_or(DecodeError::TruncatedInput)?;
+        num_members += 1;
+
+        if input.is_empty() {
+            break;
+        }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+// Prints every block of the deflate stream at the start of `data`, which is at `offset` in the
+// file, and returns the length of the stream.
+fn inspect_stream(
+    out: &mut impl Write,
+    data: &[u8],
+    offset: usize,
+    with_symbols: bool,
+) -> anyhow::Result<usize> {
+    let mut inspector = BlockInspector::new(data, with_symbols);
+    let mut blocks = Vec::new();
+
+    while let Some(result) = inspector.next() {
+        let block = result.map_err(|error| {
+            let position = offset as u64 * 8 + inspector.bit_position();
+            anyhow::anyhow!(
+                "{error} in block {} near byte {} (bit {})",
+                blocks.len(),
+                position / 8,
+                position % 8
+            )
+        })?;
+
+        print_block(out, blocks.len(), &block, offset)?;
+        blocks.push(block);
+    }
+
+    writeln!(
+        out,
+        "{:>5} {:<7} {:>12} {:>12} {:>7} {:>12}",
+        "block", "type", "header bits", "payload bits", "header", "bytes"
+    )?;
+    for (idx, block) in blocks.iter().enumerate() {
+        let total_bits = block.header_bits + block.payload_bits;
+        writeln!(
+            out,
+            "{idx:>5} {:<7} {:>12} {:>12} {:>6.1}% {:>12}",
+            block_type_name(block),
+            block.header_bits,
+            block.payload_bits,
+            100.0 * block.header_bits as f64 / total_bits as f64,
+            block.num_bytes
+        )?;
+    }
+
+    let header_bits: u64 = blocks.iter().map(|block| block.header_bits).sum();
+    let payload_bits: u64 = blocks.iter().map(|block| block.payload_bits).sum();
+    let num_bytes: u64 = blocks.iter().map(|block| block.num_bytes).sum();
+    writeln!(
+        out,
+        "{:>5} {:<7} {header_bits:>12} {payload_bits:>12} {:>6.1}% {num_bytes:>12}",
+        "total",
+        "",
+        100.0 * header_bits as f64 / (header_bits + payload_bits).max(1) as f64
+    )?;
+
+    Ok(inspector.num_bytes_read())
+}
+
+fn print_block(
+    out: &mut impl Write,
+    idx: usize,
+    block: &BlockInfo,
+    offset: usize,
+) -> std::io::Result<()> {
+    let position = offset as u64 * 8 + block.bit_offset;
+    writeln!(
+        out,
+        "block {idx} at byte {} (bit {}): {}, BFINAL={}",
+        position / 8,
+        position % 8,
+        block_type_name(block),
+        block.bfinal as u8
+    )?;
+
+    if let BlockKind::Dynamic(header) = &block.kind {
+        print_dynamic_header(out, header)?;
+    }
+
+    for info in &block.symbols {
+        let description = match info.symbol {
+            Symbol::Literal(byte) if byte.is_ascii_graphic() || byte == b' ' => {
+                format!("literal {:?}", byte as char)
+            }
+            Symbol::Literal(byte) => format!("literal {byte:#04x}"),
+            Symbol::Match { length, distance } => {
+                format!("match length {length} distance {distance}")
+            }
+            Symbol::EndOfBlock => "end of block".into(),
+        };
+        writeln!(out, "  {description:<40} {:>3} bits", info.num_bits)?;
+    }
+
+    writeln!(
+        out,
+        "  {} header bits, {} payload bits, {} bytes",
+        block.header_bits, block.payload_bits, block.num_bytes
+    )?;
+    writeln!(out)
+}
+
+fn print_dynamic_header(out: &mut impl Write, header: &DynamicHeader) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "  HLIT={} ({} codes), HDIST={} ({} codes), HCLEN={} ({} codes)",
+        header.num_literal_codes - 257,
+        header.num_literal_codes,
+        header.num_distance_codes - 1,
+        header.num_distance_codes,
+        header.num_code_length_codes - 4,
+        header.num_code_length_codes
+    )?;
+
+    writeln!(out, "  code length code lengths:")?;
+    print_code_lengths(out, &header.code_length_lengths)?;
+
+    writeln!(out, "  code length symbols:")?;
+    for symbol in &header.code_length_symbols {
+        let description = match symbol.symbol {
+            16 => format!("repeat {}", symbol.num_repeated),
+            17 | 18 => format!("zeros {}", symbol.num_repeated),
+            length => format!("lens {length}"),
+        };
+        writeln!(
+            out,
+            "    {:>2}  {description:<12} {:>2} bits",
+            symbol.symbol, symbol.num_bits
+        )?;
+    }
+
+    writeln!(out, "  literal/length code lengths:")?;
+    print_code_lengths(out, &header.literal_lengths)?;
+    writeln!(out, "  distance code lengths:")?;
+    print_code_lengths(out, &header.distance_lengths)
+}
+
+// Prints the code lengths 16 to a line, each line starting with its first symbol.
+fn print_code_lengths(out: &mut impl Write, lengths: &[u8]) -> std::io::Result<()> {
+    for (row, lengths) in lengths.chunks(16).enumerate() {
+        let lengths: Vec<String> = lengths
+            .iter()
+            .map(|length| format!("{length:>2}"))
+            .collect();
+        writeln!(out, "    {:>3}: {}", row * 16, lengths.join(" "))?;
+    }
+
+    Ok(())
+}
+
+fn block_type_name(block: &BlockInfo) -> &'static str {
+    match block.kind {
+        BlockKind::Stored => "stored",
+        BlockKind::Fixed => "fixed",
+        BlockKind::Dynamic(_) => "dynamic",
+    }
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
+    let Some(tool_args) = args.tool else {
+        return args.gzip.run().exit_code();
+    };
 
-    match args.op {
-        Operation::Compress(args) => compress(args.input_path, args.output_path),
-        Operation::Decompress(args) => decompress(args.input_path, args.output_path),
+    let tool_args = ToolArgs::parse_from(std::iter::once(OsString::from("essam")).chain(tool_args));
+    let result = match tool_args.op {
+        Operation::Compress(args) => compress(args).map(|()| Status::Success),
+        Operation::Decompress(args) => decompress(args).map(|()| Status::Success),
+        Operation::TrainDict(args) => train_dict(args).map(|()| Status::Success),
+        Operation::Test(args) => test(args).map_err(anyhow::Error::from),
+        Operation::List(args) => list(args).map_err(anyhow::Error::from),
+        Operation::Inspect(args) => inspect(args).map(|()| Status::Success),
+    };
+
+    match result {
+        Ok(status) => status.exit_code(),
+        // The output was closed early, like when it's piped into `head`, so nobody will see more.
+        Err(error) if is_broken_pipe(&error) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {error:?}");
+            ExitCode::FAILURE
+        }
     }
 }
+
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|error| error.kind() == std::io::ErrorKind::BrokenPipe)
+}
@@ -0,0 +1,149 @@
+// Implements optimal parsing of LZ77 matches, in the style of Zopfli.
+//
+// Reference: https://github.com/google/zopfli (squeeze.c)
+//
+// Shortest Path
+// =============
+// Parsing a block into literals and matches can be seen as finding a path in a graph where the
+// nodes are the positions in the block (plus one for the end), and the edges are the literals and
+// matches starting at each position. A literal goes from position i to i + 1, and a match of length
+// L goes from position i to i + L. If the weight of each edge is the number of bits needed to encode
+// it, the shortest path from the start to the end of the block is the cheapest parse.
+//
+// Since all edges go forward, the nodes are already topologically sorted, and the shortest path is
+// found with a single forward pass of dynamic programming.
+//
+// Cost Model
+// ==========
+// The number of bits for each symbol depends on the Huffman codes, which in turn depend on the parse.
+// The caller breaks this cycle by iterating: parse using the code lengths of the previous parse,
+// build the codes of the new parse, and repeat until the size stops improving.
+//
+// Matches
+// =======
+// For each position, all the matches found while walking the hash chain are kept, from the
+// shortest to the longest. Since the chain is walked from the closest position to the furthest,
+// each match is the closest one having its length, so for any length L the best distance is the one
+// of the first match at least L bytes long.
+
+use crate::lz77::{Match, MatchFinder, MAX_MATCH, MIN_MATCH, WINDOW_SIZE};
+
+pub struct CostModel {
+    pub literal_costs: [u32; 256],
+    /// Indexed by the match length, including the extra bits.
+    pub length_costs: [u32; MAX_MATCH + 1],
+    /// Indexed by the match distance, including the extra bits.
+    pub distance_costs: Vec<u32>,
+}
+
+pub struct BlockMatches {
+    matches: Vec<Match>,
+    // The matches at position i are matches[offsets[i]..offsets[i + 1]].
+    offsets: Vec<usize>,
+}
+
+impl BlockMatches {
+    /// Collects the matches at every position of the next `num_bytes` bytes, advancing the match
+    /// finder past them.
+    pub fn collect(match_finder: &mut MatchFinder, num_bytes: usize) -> Self {
+        let mut matches = Vec::new();
+        let mut offsets = Vec::with_capacity(num_bytes + 1);
+
+        offsets.push(0);
+        for _ in 0..num_bytes {
+            match_finder.find_all_matches(MAX_MATCH, &mut matches);
+            match_finder.advance(1);
+            offsets.push(matches.len());
+        }
+
+        Self { matches, offsets }
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn at(&self, pos: usize) -> &[Match] {
+        &self.matches[self.offsets[pos]..self.offsets[pos + 1]]
+    }
+}
+
+impl CostModel {
+    pub fn new() -> Self {
+        Self {
+            literal_costs: [0; 256],
+            length_costs: [0; MAX_MATCH + 1],
+            distance_costs: vec![0; WINDOW_SIZE + 1],
+        }
+    }
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Finds the cheapest parse of the bytes according to the cost model. The result has an entry for
+/// each step, which is either a literal (`None`) or a match.
+pub fn optimal_parse(
+    bytes: &[u8],
+    matches: &BlockMatches,
+    costs: &CostModel,
+) -> Vec<Option<Match>> {
+    debug_assert!(bytes.len() == matches.len());
+
+    let num_bytes = bytes.len();
+
+    // The cost of the cheapest path to each position, and the last step of that path.
+    let mut path_costs = vec![u32::MAX; num_bytes + 1];
+    let mut last_steps: Vec<Option<Match>> = vec![None; num_bytes + 1];
+
+    path_costs[0] = 0;
+
+    for pos in 0..num_bytes {
+        let cost = path_costs[pos];
+
+        let literal_cost = cost + costs.literal_costs[bytes[pos] as usize];
+        if literal_cost < path_costs[pos + 1] {
+            path_costs[pos + 1] = literal_cost;
+            last_steps[pos + 1] = None;
+        }
+
+        let mut length = MIN_MATCH;
+        for m in matches.at(pos) {
+            let distance_cost = cost + costs.distance_costs[m.distance as usize];
+
+            while length <= m.length as usize {
+                let match_cost = distance_cost + costs.length_costs[length];
+
+                if match_cost < path_costs[pos + length] {
+                    path_costs[pos + length] = match_cost;
+                    last_steps[pos + length] = Some(Match {
+                        length: length as u16,
+                        distance: m.distance,
+                    });
+                }
+
+                length += 1;
+            }
+        }
+    }
+
+    // Trace the path back from the end.
+    let mut steps = Vec::new();
+    let mut pos = num_bytes;
+
+    while pos > 0 {
+        let step = last_steps[pos];
+        pos -= step.map_or(1, |m| m.length as usize);
+        steps.push(step);
+    }
+
+    steps.reverse();
+    steps
+}
@@ -89,7 +89,7 @@ pub fn package_merge(freqs: &[u32], max_length: usize) -> Result<Vec<u8>, Packag
     let non_zero_order: &[u16];
 
     if let Some(first_non_zero) = order.iter().position(|&idx| freqs[idx as usize] != 0) {
-        non_zero_order = &order[first_non_zero as usize..order.len()];
+        non_zero_order = &order[first_non_zero..order.len()];
     } else {
         return Ok(vec![0; freqs.len()]);
     }
@@ -112,7 +112,7 @@ pub fn package_merge(freqs: &[u32], max_length: usize) -> Result<Vec<u8>, Packag
     } else if max_symbols_given_length == num_symbols {
         return Ok(freqs
             .iter()
-            .map(|&freq| if freq != 0 { max_length as u8 } else { 0 as u8 })
+            .map(|&freq| if freq != 0 { max_length as u8 } else { 0_u8 })
             .collect());
     }
 
@@ -0,0 +1,495 @@
+// Implements the zlib format, a lighter wrapper around deflate than gzip.
+//
+// Reference: RFC1950.
+//
+// Format
+// ======
+// A zlib stream is a two byte header, an optional dictionary id, the deflate stream, and the
+// Adler-32 of the uncompressed data in big-endian order. The first header byte (CMF) holds the
+// compression method in its low nibble and the base-2 logarithm of the window size minus 8 in its
+// high nibble. The second byte (FLG) holds a hint of the compression level (FLEVEL), the FDICT
+// flag, and FCHECK, which is chosen so that CMF * 256 + FLG is a multiple of 31.
+//
+// Adler-32
+// ========
+// The checksum is two sums modulo 65521, the largest prime below 2^16: `a` is one plus the sum of
+// the bytes, and `b` the sum of the successive values of `a`. The modulo only needs to be taken
+// every NMAX bytes, the largest count for which `b` can't overflow 32 bits.
+
+use crate::deflate::{
+    compress as deflate_compress, decompress_with_dictionary as deflate_decompress, DeflateOptions,
+};
+use crate::error::DecodeError;
+use std::io::{Read, Seek, Write};
+
+const DEFLATE_CM: u8 = 8;
+const MAX_WINDOW_BITS: u8 = 15;
+const FDICT_MASK: u8 = 0b00100000;
+
+const ADLER_MOD: u32 = 65521;
+const NMAX: usize = 5552;
+
+/// The header of a zlib stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZlibHeader {
+    /// The base-2 logarithm of the window size, from 8 to 15.
+    pub window_bits: u8,
+    /// How hard the compressor tried, from 0 (fastest) to 3 (slowest), as a hint for recompression.
+    pub level: u8,
+    /// The Adler-32 of the preset dictionary, if one was used.
+    pub dict_id: Option<u32>,
+}
+
+/// Computes the Adler-32 checksum of the data it is given.
+#[derive(Debug, Clone, Copy)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+// Computes the Adler-32 of everything read through it.
+struct AdlerReader<R: Read> {
+    reader: R,
+    adler: Adler32,
+}
+
+// Computes the Adler-32 of everything written through it.
+struct AdlerWriter<W: Write> {
+    writer: W,
+    adler: Adler32,
+}
+
+/// Compresses the data into a zlib stream. If the options have a preset dictionary, its id is
+/// stored in the header.
+pub fn compress(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    options: DeflateOptions,
+) -> std::io::Result<()> {
+    let header = ZlibHeader {
+        window_bits: MAX_WINDOW_BITS,
+        level: ZlibHeader::level_from_options(&options),
+        dict_id: options.dictionary.as_deref().map(dictionary_id),
+    };
+    header.write(writer)?;
+
+    let mut reader = AdlerReader {
+        reader,
+        adler: Adler32::new(),
+    };
+    deflate_compress(&mut reader, writer, options)?;
+
+    writer.write_all(&reader.adler.finish().to_be_bytes())?;
+    writer.flush()
+}
+
+/// Decompresses a zlib stream, and returns its header. The bytes that were read after the end of
+/// the stream are put back by seeking the reader. The dictionary is only used if the header asks
+/// for one, and then it must have the right id.
+pub fn decompress(
+    reader: &mut (impl Read + Seek),
+    writer: &mut impl Write,
+    dictionary: Option<&[u8]>,
+) -> Result<ZlibHeader, DecodeError> {
+    let header = ZlibHeader::read(reader)?;
+
+    let dictionary = match (header.dict_id, dictionary) {
+        (None, _) => &[][..],
+        (Some(expected), None) => return Err(DecodeError::DictionaryRequired(expected)),
+        (Some(expected), Some(dictionary)) => {
+            let actual = dictionary_id(dictionary);
+            if expected != actual {
+                return Err(DecodeError::DictionaryMismatch { expected, actual });
+            }
+
+            dictionary
+        }
+    };
+
+    let mut writer = AdlerWriter {
+        writer,
+        adler: Adler32::new(),
+    };
+    deflate_decompress(reader, &mut writer, dictionary)?;
+
+    let mut trailer = [0; 4];
+    reader.read_exact(&mut trailer)?;
+
+    let expected = u32::from_be_bytes(trailer);
+    let actual = writer.adler.finish();
+    if expected != actual {
+        return Err(DecodeError::AdlerMismatch { expected, actual });
+    }
+
+    Ok(header)
+}
+
+/// The id stored in the header for a preset dictionary, which is its Adler-32. Only the last 32 KiB
+/// are used by the compressor, but the id covers the whole dictionary.
+pub fn dictionary_id(dictionary: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.update(dictionary);
+    adler.finish()
+}
+
+impl ZlibHeader {
+    /// Reads and checks a header, along with the dictionary id if there's one.
+    pub fn read(reader: &mut impl Read) -> Result<Self, DecodeError> {
+        let mut bytes = [0; 2];
+        reader.read_exact(&mut bytes)?;
+        let [cmf, flg] = bytes;
+
+        if u16::from_be_bytes(bytes) % 31 != 0 {
+            return Err(DecodeError::HeaderCheckFailed);
+        }
+        if cmf & 0x0f != DEFLATE_CM {
+            return Err(DecodeError::UnknownCompressionMethod(cmf & 0x0f));
+        }
+
+        let window_bits = (cmf >> 4) + 8;
+        if window_bits > MAX_WINDOW_BITS {
+            return Err(DecodeError::InvalidWindowSize(window_bits));
+        }
+
+        let dict_id = if flg & FDICT_MASK != 0 {
+            let mut dict_id = [0; 4];
+            reader.read_exact(&mut dict_id)?;
+            Some(u32::from_be_bytes(dict_id))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            window_bits,
+            level: flg >> 6,
+            dict_id,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        if !(8..=MAX_WINDOW_BITS).contains(&self.window_bits) || self.level > 3 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid zlib header",
+            ));
+        }
+
+        let cmf = ((self.window_bits - 8) << 4) | DEFLATE_CM;
+        let mut flg = self.level << 6;
+        if self.dict_id.is_some() {
+            flg |= FDICT_MASK;
+        }
+
+        // Make the header a multiple of 31.
+        let remainder = (cmf as u16 * 256 + flg as u16) % 31;
+        if remainder != 0 {
+            flg += (31 - remainder) as u8;
+        }
+
+        writer.write_all(&[cmf, flg])?;
+        if let Some(dict_id) = self.dict_id {
+            writer.write_all(&dict_id.to_be_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    // Maps the levels the same way as zlib.
+    fn level_from_options(options: &DeflateOptions) -> u8 {
+        match options.level {
+            _ if options.ultra => 3,
+            0..=1 => 0,
+            2..=5 => 1,
+            6 => 2,
+            _ => 3,
+        }
+    }
+}
+
+impl Adler32 {
+    pub fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(NMAX) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+
+            self.a %= ADLER_MOD;
+            self.b %= ADLER_MOD;
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Read> Read for AdlerReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let num_bytes = self.reader.read(buf)?;
+        self.adler.update(&buf[0..num_bytes]);
+        Ok(num_bytes)
+    }
+}
+
+impl<W: Write> Write for AdlerWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let num_bytes = self.writer.write(buf)?;
+        self.adler.update(&buf[0..num_bytes]);
+        Ok(num_bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const DATA: &[u8] = b"hello, hello, hello world";
+
+    fn compressed(data: &[u8], options: DeflateOptions) -> Vec<u8> {
+        let mut output = Vec::new();
+        compress(&mut &data[..], &mut output, options).unwrap();
+
+        output
+    }
+
+    // Builds the two header bytes, with FCHECK set so that they pass the check.
+    fn header_bytes(cmf: u8, flg: u8) -> [u8; 2] {
+        let remainder = (cmf as u16 * 256 + flg as u16) % 31;
+        let flg = if remainder == 0 {
+            flg
+        } else {
+            flg + (31 - remainder) as u8
+        };
+
+        [cmf, flg]
+    }
+
+    fn adler32(bytes: &[u8]) -> u32 {
+        let mut adler = Adler32::new();
+        adler.update(bytes);
+        adler.finish()
+    }
+
+    #[test]
+    fn adler32_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"a"), 0x00620062);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn adler32_past_nmax() {
+        // Enough 0xff bytes that the sums would overflow if the modulo wasn't taken every NMAX.
+        let bytes = vec![0xff; 3 * NMAX + 7];
+
+        let mut a = 1u64;
+        let mut b = 0u64;
+        for &byte in &bytes {
+            a = (a + byte as u64) % ADLER_MOD as u64;
+            b = (b + a) % ADLER_MOD as u64;
+        }
+        assert_eq!(adler32(&bytes), ((b << 16) | a) as u32);
+
+        // Updating in pieces gives the same checksum as all at once.
+        let mut adler = Adler32::new();
+        for chunk in bytes.chunks(1000) {
+            adler.update(chunk);
+        }
+        assert_eq!(adler.finish(), adler32(&bytes));
+    }
+
+    #[test]
+    fn round_trip() {
+        let stream = compressed(DATA, DeflateOptions::default());
+
+        assert_eq!(&stream[..2], [0x78, 0x9c]);
+        assert_eq!(stream[stream.len() - 4..], adler32(DATA).to_be_bytes());
+
+        let mut output = Vec::new();
+        let header = decompress(&mut Cursor::new(&stream), &mut output, None).unwrap();
+
+        assert_eq!(output, DATA);
+        assert_eq!(
+            header,
+            ZlibHeader {
+                window_bits: 15,
+                level: 2,
+                dict_id: None,
+            }
+        );
+    }
+
+    #[test]
+    fn header_round_trip() {
+        for window_bits in 8..=MAX_WINDOW_BITS {
+            for level in 0..=3 {
+                for dict_id in [None, Some(0x11e60398)] {
+                    let header = ZlibHeader {
+                        window_bits,
+                        level,
+                        dict_id,
+                    };
+                    let mut bytes = Vec::new();
+                    header.write(&mut bytes).unwrap();
+
+                    assert_eq!(u16::from_be_bytes([bytes[0], bytes[1]]) % 31, 0);
+                    assert_eq!(ZlibHeader::read(&mut &bytes[..]).unwrap(), header);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stream_is_followed_by_more_data() {
+        let mut stream = compressed(DATA, DeflateOptions::default());
+        let length = stream.len();
+        stream.extend_from_slice(b"more");
+
+        let mut reader = Cursor::new(&stream);
+        let mut output = Vec::new();
+        decompress(&mut reader, &mut output, None).unwrap();
+
+        assert_eq!(output, DATA);
+        assert_eq!(reader.position(), length as u64);
+    }
+
+    #[test]
+    fn header_check_failed() {
+        let mut stream = compressed(DATA, DeflateOptions::default());
+        stream[1] ^= 1;
+
+        let error = decompress(&mut Cursor::new(&stream), &mut Vec::new(), None).unwrap_err();
+
+        assert!(matches!(error, DecodeError::HeaderCheckFailed), "{error:?}");
+    }
+
+    #[test]
+    fn unknown_compression_method() {
+        let bytes = header_bytes(0x7f, 0);
+
+        let error = ZlibHeader::read(&mut &bytes[..]).unwrap_err();
+
+        assert!(
+            matches!(error, DecodeError::UnknownCompressionMethod(15)),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn invalid_window_size() {
+        let bytes = header_bytes(0x88, 0);
+
+        let error = ZlibHeader::read(&mut &bytes[..]).unwrap_err();
+
+        assert!(
+            matches!(error, DecodeError::InvalidWindowSize(16)),
+            "{error:?}"
+        );
+
+        let header = ZlibHeader {
+            window_bits: 16,
+            level: 0,
+            dict_id: None,
+        };
+        assert!(header.write(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn adler_mismatch() {
+        let mut stream = compressed(DATA, DeflateOptions::default());
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+
+        let error = decompress(&mut Cursor::new(&stream), &mut Vec::new(), None).unwrap_err();
+
+        match error {
+            DecodeError::AdlerMismatch { expected, actual } => {
+                assert_eq!(actual, adler32(DATA));
+                assert_eq!(expected, actual ^ 1);
+            }
+            error => panic!("{error:?}"),
+        }
+    }
+
+    #[test]
+    fn dictionary_round_trip() {
+        let dictionary = b"hello world, ".repeat(3);
+        let options = DeflateOptions {
+            dictionary: Some(dictionary.clone()),
+            ..Default::default()
+        };
+        let stream = compressed(DATA, options);
+
+        let mut output = Vec::new();
+        let header = decompress(&mut Cursor::new(&stream), &mut output, Some(&dictionary)).unwrap();
+
+        assert_eq!(output, DATA);
+        assert_eq!(header.dict_id, Some(dictionary_id(&dictionary)));
+        assert_eq!(stream[2..6], dictionary_id(&dictionary).to_be_bytes());
+    }
+
+    #[test]
+    fn dictionary_required() {
+        let dictionary = b"hello world, ".to_vec();
+        let options = DeflateOptions {
+            dictionary: Some(dictionary.clone()),
+            ..Default::default()
+        };
+        let stream = compressed(DATA, options);
+
+        let error = decompress(&mut Cursor::new(&stream), &mut Vec::new(), None).unwrap_err();
+
+        assert!(
+            matches!(error, DecodeError::DictionaryRequired(id) if id == dictionary_id(&dictionary)),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn dictionary_mismatch() {
+        let dictionary = b"hello world, ".to_vec();
+        let options = DeflateOptions {
+            dictionary: Some(dictionary.clone()),
+            ..Default::default()
+        };
+        let stream = compressed(DATA, options);
+
+        let other = b"goodbye world, ";
+        let error =
+            decompress(&mut Cursor::new(&stream), &mut Vec::new(), Some(other)).unwrap_err();
+
+        match error {
+            DecodeError::DictionaryMismatch { expected, actual } => {
+                assert_eq!(expected, dictionary_id(&dictionary));
+                assert_eq!(actual, dictionary_id(other));
+            }
+            error => panic!("{error:?}"),
+        }
+    }
+
+    #[test]
+    fn dictionary_is_ignored_when_not_asked_for() {
+        let stream = compressed(DATA, DeflateOptions::default());
+
+        let mut output = Vec::new();
+        decompress(&mut Cursor::new(&stream), &mut output, Some(b"unused")).unwrap();
+
+        assert_eq!(output, DATA);
+    }
+}